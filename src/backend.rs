@@ -6,75 +6,1352 @@
 
 mod types;
 
-use std::future;
+use std::{
+    collections::{HashMap, HashSet},
+    io::ErrorKind,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Arc,
+    time::Duration,
+};
 
-use futures::{StreamExt, TryFutureExt};
-use tokio::{sync::watch, task::JoinSet};
-use tokio_stream::wrappers::WatchStream;
+use futures::{FutureExt, future::BoxFuture};
+use tokio::{
+    process::Command,
+    sync::{mpsc, watch},
+    task::{self, JoinError, JoinSet},
+};
 use tracing::{Instrument, Level, event, span};
 
-pub use types::ColorScheme;
+use crate::{
+    config::Config,
+    state::{AppliedScheme, AppliedSchemes},
+};
+
+pub use types::{ColorScheme, Diagnosis};
+pub mod alacritty;
+pub mod apply;
+pub mod emacs;
+pub mod foot;
+pub mod ghostty;
 pub mod gtk;
 pub mod helix;
+pub mod jsonc;
+pub mod kitty;
+pub mod log;
+pub mod msgpack;
+pub mod neovim;
+pub mod process;
+pub mod theme;
+pub mod vim;
+pub mod vscode;
+pub mod wezterm;
+pub mod zed;
 
-/// Spawn tasks for all backends which receive colour scheme changes.
+/// A backend which applies colour schemes to some application.
 ///
-/// Spawn tasks for the following backends:
+/// Backends are registered in a [`Registry`] and then spawned with
+/// [`spawn_backends`] which takes care of feeding colour scheme changes into
+/// the backend, and of logging and error handling.
+pub trait Backend: Send + Sync {
+    /// The name of this backend.
+    ///
+    /// Used to identify the backend in log messages and spans.
+    fn name(&self) -> &str;
+
+    /// Initialize this backend.
+    ///
+    /// Called once before the backend receives its first colour scheme.  If
+    /// initialization fails the backend does not receive any colour schemes.
+    ///
+    /// The default implementation does nothing.
+    fn init(&self) -> BoxFuture<'_, std::io::Result<()>> {
+        futures::future::ok(()).boxed()
+    }
+
+    /// Apply the given colour scheme.
+    fn apply(&self, color_scheme: ColorScheme) -> BoxFuture<'_, std::io::Result<()>>;
+
+    /// Shut this backend down.
+    ///
+    /// Called once after the backend stopped receiving colour schemes.
+    ///
+    /// The default implementation does nothing.
+    fn shutdown(&self) -> BoxFuture<'_, std::io::Result<()>> {
+        futures::future::ok(()).boxed()
+    }
+
+    /// Check whether the environment lets this backend work.
+    ///
+    /// Return the result of every check this backend made.
+    ///
+    /// The default implementation checks nothing.
+    fn diagnose(&self) -> BoxFuture<'_, Vec<Diagnosis>> {
+        futures::future::ready(Vec::new()).boxed()
+    }
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+/// Find the executable `program` in `$PATH`.
+pub fn find_program(program: &str) -> Option<PathBuf> {
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|directory| directory.join(program))
+        .find(|path| is_executable(path))
+}
+
+/// Diagnose whether `program` exists in `$PATH`.
 ///
-/// - A log backend which simply logs the new colour scheme via tracing.
-/// - A Gtk backend which changes the legacy Gtk theme setting accordingly.
+/// Suggest to install `package` if it does not.
+pub fn diagnose_program(program: &str, package: &str) -> Diagnosis {
+    match find_program(program) {
+        Some(path) => Diagnosis::Passed(format!("{program} found at {}", path.display())),
+        None => Diagnosis::failed(
+            format!("{program} not found in $PATH"),
+            format!("Install {package}, or add the directory containing {program} to $PATH"),
+        ),
+    }
+}
+
+/// Run `command` and wait for it to finish.
 ///
-/// Provide the given colour scheme receiver to each backend to listen for
-/// colour scheme changes independently.
+/// Discard the output of `command`, and kill `command` if the returned future
+/// gets dropped, e.g. because applying a colour scheme timed out.
 ///
-/// Return a join set which represents all running backend tasks.
-pub fn spawn_backends(color_scheme_rx: &watch::Receiver<ColorScheme>) -> JoinSet<()> {
-    let backends_span = span!(Level::INFO, "backends").or_current();
-    let mut backends = JoinSet::new();
-    backends.spawn(
-        WatchStream::from_changes(color_scheme_rx.clone())
-            .for_each(|color_scheme| {
-                event!(Level::INFO, task.id = %tokio::task::id(), "Color scheme updated to {color_scheme:?}");
-                future::ready(())
-            })
-            .instrument(span!(parent: &backends_span, Level::INFO, "backend.log")),
-    );
-    backends.spawn(
-        WatchStream::from_changes(color_scheme_rx.clone())
-            .for_each(|color_scheme| {
-                gtk::apply_color_scheme(color_scheme)
-                    .inspect_err(move |error| {
+/// Fail if `command` exits with a non-zero status; include the standard error
+/// of `command` in the error.
+pub async fn run_command(command: &mut Command) -> std::io::Result<()> {
+    run_command_with_stdout(command, Stdio::null())
+        .await
+        .map(|_| ())
+}
+
+/// Run `command` and return its standard output.
+///
+/// Like [`run_command`], but capture the standard output of `command`, and
+/// fail if it's not valid UTF-8.
+pub async fn command_output(command: &mut Command) -> std::io::Result<String> {
+    let stdout = run_command_with_stdout(command, Stdio::piped()).await?;
+    String::from_utf8(stdout).map_err(|error| {
+        std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("{command:?} printed invalid UTF-8: {error}"),
+        )
+    })
+}
+
+async fn run_command_with_stdout(command: &mut Command, stdout: Stdio) -> std::io::Result<Vec<u8>> {
+    let output = command
+        .kill_on_drop(true)
+        .stdout(stdout)
+        .stdin(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?
+        .wait_with_output()
+        .await?;
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(std::io::Error::other(format!(
+            "{command:?} failed with status {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        )))
+    }
+}
+
+/// Run the blocking `f` on the blocking thread pool, and return its result.
+///
+/// We never abort blocking tasks, so the task can only fail if `f` panicked;
+/// resume the panic in the calling task in this case.
+pub async fn spawn_blocking_io<T, F>(f: F) -> std::io::Result<T>
+where
+    F: FnOnce() -> std::io::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(error) => std::panic::resume_unwind(error.into_panic()),
+    }
+}
+
+/// A backend in a [`Registry`].
+#[derive(Clone)]
+struct Registered {
+    backend: Arc<dyn Backend>,
+    apply: apply::Config,
+    /// The fingerprint of the configuration of the backend.
+    fingerprint: String,
+}
+
+/// Compute a fingerprint of the backend configuration `config`.
+///
+/// Hash the debug representation of `config` with 64-bit FNV-1a, which unlike
+/// the hasher of the standard library is stable across Rust releases.
+fn fingerprint(config: &impl std::fmt::Debug) -> String {
+    let hash = format!("{config:?}")
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    format!("{hash:016x}")
+}
+
+/// A registry of backends.
+#[derive(Default)]
+pub struct Registry {
+    backends: Vec<Registered>,
+}
+
+impl Registry {
+    /// Create a registry with all builtin backends enabled in `config`.
+    ///
+    /// The following builtin backends exist:
+    ///
+    /// - A log backend which simply logs the new colour scheme via tracing.
+    /// - A Gtk backend which changes the legacy Gtk theme setting accordingly.
+    /// - A Helix backend which switches the Helix theme.
+    /// - A kitty backend which switches the kitty theme.
+    /// - An Alacritty backend which switches the Alacritty theme.
+    /// - A foot backend which switches the foot palette.
+    /// - A WezTerm backend which updates a Lua module with the colour scheme.
+    /// - A Ghostty backend which switches the Ghostty theme.
+    /// - A Neovim backend which runs a command in all Neovim instances.
+    /// - An Emacs backend which evaluates a form in all Emacs servers.
+    /// - A VS Code backend which changes the theme in the VS Code settings.
+    /// - A Zed backend which changes the theme in the Zed settings.
+    /// - A Vim backend which runs a command in all Vim servers.
+    ///
+    /// If `dry_run` is true backends only log what they would do, without
+    /// changing anything.
+    pub fn from_config(config: &Config, dry_run: bool) -> Self {
+        let mut registry = Self::default();
+        if config.log.enabled {
+            registry.register(log::Log, &config.log, config.log.apply.clone());
+        }
+        if config.gtk.enabled {
+            registry.register(
+                gtk::Gtk::new(&config.gtk, dry_run),
+                &config.gtk,
+                config.gtk.apply.clone(),
+            );
+        }
+        if config.helix.enabled {
+            registry.register(
+                helix::Helix::new(&config.helix, dry_run),
+                &config.helix,
+                config.helix.apply.clone(),
+            );
+        }
+        if config.kitty.enabled {
+            registry.register(
+                kitty::Kitty::new(&config.kitty, dry_run),
+                &config.kitty,
+                config.kitty.apply.clone(),
+            );
+        }
+        if config.alacritty.enabled {
+            registry.register(
+                alacritty::Alacritty::new(&config.alacritty, dry_run),
+                &config.alacritty,
+                config.alacritty.apply.clone(),
+            );
+        }
+        if config.foot.enabled {
+            registry.register(
+                foot::Foot::new(&config.foot, dry_run),
+                &config.foot,
+                config.foot.apply.clone(),
+            );
+        }
+        if config.wezterm.enabled {
+            registry.register(
+                wezterm::WezTerm::new(&config.wezterm, dry_run),
+                &config.wezterm,
+                config.wezterm.apply.clone(),
+            );
+        }
+        if config.ghostty.enabled {
+            registry.register(
+                ghostty::Ghostty::new(&config.ghostty, dry_run),
+                &config.ghostty,
+                config.ghostty.apply.clone(),
+            );
+        }
+        if config.neovim.enabled {
+            registry.register(
+                neovim::Neovim::new(&config.neovim, dry_run),
+                &config.neovim,
+                config.neovim.apply.clone(),
+            );
+        }
+        if config.emacs.enabled {
+            registry.register(
+                emacs::Emacs::new(&config.emacs, dry_run),
+                &config.emacs,
+                config.emacs.apply.clone(),
+            );
+        }
+        if config.vscode.enabled {
+            registry.register(
+                vscode::VsCode::new(&config.vscode, dry_run),
+                &config.vscode,
+                config.vscode.apply.clone(),
+            );
+        }
+        if config.zed.enabled {
+            registry.register(
+                zed::Zed::new(&config.zed, dry_run),
+                &config.zed,
+                config.zed.apply.clone(),
+            );
+        }
+        if config.vim.enabled {
+            registry.register(
+                vim::Vim::new(&config.vim, dry_run),
+                &config.vim,
+                config.vim.apply.clone(),
+            );
+        }
+        registry
+    }
+
+    /// The names of all registered backends.
+    pub fn names(&self) -> Vec<String> {
+        self.backends
+            .iter()
+            .map(|registered| registered.backend.name().to_owned())
+            .collect()
+    }
+
+    /// Register a new `backend` created from `config`, which applies colour
+    /// schemes according to `apply`.
+    ///
+    /// Backends remember a fingerprint of `config` with every colour scheme
+    /// they applied, to apply the colour scheme again if `config` changed.
+    pub fn register<B: Backend + 'static>(
+        &mut self,
+        backend: B,
+        config: &impl std::fmt::Debug,
+        apply: apply::Config,
+    ) -> &mut Self {
+        self.backends.push(Registered {
+            backend: Arc::new(backend),
+            apply,
+            fingerprint: fingerprint(config),
+        });
+        self
+    }
+}
+
+async fn init_backend(backend: &dyn Backend) -> std::io::Result<()> {
+    backend.init().await.inspect_err(|error| {
+        event!(
+            Level::ERROR,
+            "Failed to initialize backend {}: {error}",
+            backend.name()
+        );
+    })
+}
+
+async fn apply_to_backend(
+    backend: &dyn Backend,
+    config: &apply::Config,
+    color_scheme: ColorScheme,
+) -> std::io::Result<()> {
+    let timeout = config.timeout();
+    tokio::time::timeout(timeout, backend.apply(color_scheme))
+        .await
+        .unwrap_or_else(|_| {
+            Err(std::io::Error::new(
+                ErrorKind::TimedOut,
+                format!("Timed out after {timeout:?}"),
+            ))
+        })
+        .inspect_err(|error| {
+            event!(
+                Level::ERROR,
+                "Failed to apply color scheme {color_scheme:?} to {}: {error}",
+                backend.name()
+            );
+        })
+}
+
+/// How [`apply_with_retries`] ended.
+enum Attempt {
+    /// The backend finished applying the colour scheme, with the result of the
+    /// last attempt.
+    Done(std::io::Result<()>),
+    /// A newer colour scheme arrived while the backend was applying the colour
+    /// scheme, so we cancelled it.
+    Superseded,
+}
+
+/// Apply `color_scheme` to `backend`, and retry according to `config` upon failure.
+///
+/// Give every attempt the timeout of `config`, and wait with exponential
+/// backoff between retries.  Cancel any attempt and give up retrying as soon as
+/// `superseded` completes, i.e. when a newer colour scheme arrived.  Give up
+/// retrying, but let the current attempt finish, when `stopped` completes.
+///
+/// Return the result of the last attempt, or [`Attempt::Superseded`] if we
+/// cancelled an attempt.
+async fn apply_with_retries(
+    backend: &dyn Backend,
+    config: &apply::Config,
+    color_scheme: ColorScheme,
+    superseded: impl Future<Output = ()>,
+    stopped: impl Future<Output = ()>,
+) -> Attempt {
+    let mut retry_delays = config.retry_delays();
+    tokio::pin!(superseded, stopped);
+    loop {
+        let result = tokio::select! {
+            biased;
+            () = &mut superseded => {
+                event!(
+                    Level::INFO,
+                    "Cancelling stale apply of color scheme {color_scheme:?} to {}",
+                    backend.name()
+                );
+                return Attempt::Superseded;
+            }
+            result = apply_to_backend(backend, config, color_scheme) => result,
+        };
+        match (result, retry_delays.next()) {
+            (Ok(()), _) => return Attempt::Done(Ok(())),
+            (Err(error), None) => return Attempt::Done(Err(error)),
+            (Err(error), Some(delay)) => {
+                event!(
+                    Level::INFO,
+                    "Retrying to apply color scheme {color_scheme:?} to {} in {delay:?}",
+                    backend.name()
+                );
+                tokio::select! {
+                    biased;
+                    () = &mut superseded => {
                         event!(
-                            Level::ERROR,
-                            "Failed to apply color scheme {color_scheme:?} to Gtk: {error}"
+                            Level::INFO,
+                            "Not retrying to apply color scheme {color_scheme:?} to {}, superseded by a newer color scheme",
+                            backend.name()
                         );
-                    })
-                    .unwrap_or_else(|_| ())
-                    .instrument(
-                        span!(Level::INFO, "backend.gtk", task.id = %tokio::task::id())
-                            .or_current(),
-                    )
-            })
-            .instrument(span!(parent: &backends_span, Level::INFO, "backend.gtk")),
-    );
-    backends.spawn(
-        WatchStream::from_changes(color_scheme_rx.clone())
-            .for_each(|color_scheme| {
-                helix::apply_color_scheme(color_scheme)
-                    .inspect_err(move |error| {
+                        return Attempt::Done(Err(error));
+                    }
+                    () = &mut stopped => {
                         event!(
-                            Level::ERROR,
-                            "Failed to apply color scheme {color_scheme:?} to helix: {error}"
+                            Level::INFO,
+                            "Not retrying to apply color scheme {color_scheme:?} to {}, backend stopped",
+                            backend.name()
                         );
-                    })
-                    .unwrap_or_else(|_| ())
-                    .instrument(
-                        span!(Level::INFO, "backend.helix", task.id = %tokio::task::id())
-                            .or_current(),
+                        return Attempt::Done(Err(error));
+                    }
+                    () = tokio::time::sleep(delay) => {}
+                }
+            }
+        }
+    }
+}
+
+/// Wait until a colour scheme arrives which `color_scheme_rx` did not see yet.
+///
+/// Work on a clone of the receiver, to leave the new colour scheme unseen for
+/// the original receiver.  Never complete if the sender is gone.
+fn newer_color_scheme(
+    color_scheme_rx: &watch::Receiver<ColorScheme>,
+) -> impl Future<Output = ()> + use<> {
+    let mut color_scheme_rx = color_scheme_rx.clone();
+    async move {
+        if color_scheme_rx.changed().await.is_err() {
+            futures::future::pending::<()>().await;
+        }
+    }
+}
+
+/// Wait until `stop_rx` asks to stop.
+///
+/// Work on a clone of the receiver, to leave the stop request unseen for the
+/// original receiver.
+fn stop_requested(stop_rx: &watch::Receiver<()>) -> impl Future<Output = ()> + use<> {
+    let mut stop_rx = stop_rx.clone();
+    async move {
+        // Any change to the stop channel means we should stop, and so does
+        // the sender going away.
+        let _ = stop_rx.changed().await;
+    }
+}
+
+async fn shutdown_backend(backend: &dyn Backend) -> std::io::Result<()> {
+    backend.shutdown().await.inspect_err(|error| {
+        event!(
+            Level::ERROR,
+            "Failed to shut down backend {}: {error}",
+            backend.name()
+        );
+    })
+}
+
+/// Wait until `color_scheme_rx` sees no new colour scheme for `window`.
+///
+/// Mark every colour scheme arriving meanwhile as seen, so that only the last
+/// colour scheme of a burst gets applied.  Stop waiting early if `stop_rx` asks
+/// to stop or if the sender is gone.
+async fn settle(
+    color_scheme_rx: &mut watch::Receiver<ColorScheme>,
+    stop_rx: &watch::Receiver<()>,
+    window: Duration,
+) {
+    if window.is_zero() {
+        return;
+    }
+    let stopped = stop_requested(stop_rx);
+    tokio::pin!(stopped);
+    loop {
+        tokio::select! {
+            biased;
+            () = &mut stopped => return,
+            changed = color_scheme_rx.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                event!(
+                    Level::DEBUG,
+                    "Color scheme changed again to {:?} within {window:?}, waiting for it to settle",
+                    *color_scheme_rx.borrow()
+                );
+            }
+            () = tokio::time::sleep(window) => return,
+        }
+    }
+}
+
+/// A backend finished applying a colour scheme.
+#[derive(Debug, Clone)]
+pub struct Applied {
+    /// The name of the backend.
+    pub backend: String,
+    /// The colour scheme the backend applied.
+    pub color_scheme: ColorScheme,
+    /// Whether the backend succeeded, or gave up after all retries.
+    pub succeeded: bool,
+}
+
+/// Whether `backend` applied `color_scheme` already with the configuration
+/// identified by `fingerprint`, according to `applied_schemes`.
+fn is_up_to_date(
+    backend: &dyn Backend,
+    fingerprint: &str,
+    applied_schemes: Option<&AppliedSchemes>,
+    color_scheme: ColorScheme,
+) -> bool {
+    let Some(applied) = applied_schemes.and_then(|schemes| schemes.get(backend.name())) else {
+        return false;
+    };
+    if applied.fingerprint != fingerprint {
+        event!(
+            Level::INFO,
+            "Configuration of backend {} changed since it last applied a color scheme",
+            backend.name()
+        );
+        return false;
+    }
+    let up_to_date = applied.color_scheme == color_scheme;
+    if up_to_date {
+        event!(
+            Level::INFO,
+            "Backend {} applied color scheme {color_scheme:?} already, skipping",
+            backend.name()
+        );
+    }
+    up_to_date
+}
+
+/// Remember in `applied_schemes` that `backend` applied `color_scheme` with
+/// the configuration identified by `fingerprint`.
+fn remember_applied(
+    backend: &dyn Backend,
+    fingerprint: &str,
+    applied_schemes: Option<&AppliedSchemes>,
+    color_scheme: ColorScheme,
+) {
+    if let Some(applied_schemes) = applied_schemes {
+        let applied = AppliedScheme {
+            color_scheme,
+            fingerprint: fingerprint.to_owned(),
+        };
+        if let Err(error) = applied_schemes.set(backend.name(), &applied) {
+            event!(
+                Level::WARN,
+                "Failed to remember that {} applied color scheme {color_scheme:?}: {error}",
+                backend.name()
+            );
+        }
+    }
+}
+
+async fn run_backend(
+    registered: Registered,
+    options: SpawnOptions,
+    mut color_scheme_rx: watch::Receiver<ColorScheme>,
+    mut stop_rx: watch::Receiver<()>,
+    debounce_rx: watch::Receiver<Duration>,
+    applied_tx: mpsc::UnboundedSender<Applied>,
+) -> std::io::Result<()> {
+    let Registered {
+        backend,
+        apply: config,
+        fingerprint,
+    } = registered;
+    init_backend(backend.as_ref()).await?;
+    let applied_schemes = options.applied_schemes.as_ref();
+    // Only skip the first colour scheme; after that the backend applies every
+    // colour scheme it receives.
+    let mut skip_up_to_date = options.skip_up_to_date;
+    loop {
+        tokio::select! {
+            biased;
+            // Any change to the stop channel means we should stop
+            _ = stop_rx.changed() => break,
+            changed = color_scheme_rx.changed() => {
+                if changed.is_err() {
+                    // The sender is gone, so no more colour schemes will arrive
+                    break;
+                }
+                let debounce = *debounce_rx.borrow();
+                settle(&mut color_scheme_rx, &stop_rx, debounce).await;
+                let color_scheme = *color_scheme_rx.borrow_and_update();
+                let attempt = if std::mem::take(&mut skip_up_to_date)
+                    && is_up_to_date(backend.as_ref(), &fingerprint, applied_schemes, color_scheme)
+                {
+                    Attempt::Done(Ok(()))
+                } else {
+                    // Errors are logged already, and we'd like to continue with
+                    // the next colour scheme anyway.
+                    apply_with_retries(
+                        backend.as_ref(),
+                        &config,
+                        color_scheme,
+                        newer_color_scheme(&color_scheme_rx),
+                        stop_requested(&stop_rx),
                     )
+                    .await
+                };
+                // Don't report colour schemes superseded by newer ones
+                if let Attempt::Done(result) = attempt {
+                    if result.is_ok() {
+                        remember_applied(backend.as_ref(), &fingerprint, applied_schemes, color_scheme);
+                    }
+                    // If no one listens for reports anymore we don't care
+                    let _ = applied_tx.send(Applied {
+                        backend: backend.name().to_owned(),
+                        color_scheme,
+                        succeeded: result.is_ok(),
+                    });
+                }
+            }
+        }
+    }
+    shutdown_backend(backend.as_ref()).await
+}
+
+/// What happened to a colour scheme applied with [`apply_once`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The backend applied the colour scheme.
+    Applied,
+    /// The backend applied the colour scheme already, so we skipped it.
+    UpToDate,
+}
+
+async fn apply_backend_once(
+    registered: &Registered,
+    options: &SpawnOptions,
+    color_scheme: ColorScheme,
+) -> std::io::Result<Outcome> {
+    let backend = registered.backend.as_ref();
+    let config = &registered.apply;
+    let fingerprint = &registered.fingerprint;
+    let applied_schemes = options.applied_schemes.as_ref();
+    if options.skip_up_to_date && is_up_to_date(backend, fingerprint, applied_schemes, color_scheme)
+    {
+        return Ok(Outcome::UpToDate);
+    }
+    init_backend(backend).await?;
+    // Nothing supersedes the colour scheme, so the backend always finishes
+    let Attempt::Done(result) = apply_with_retries(
+        backend,
+        config,
+        color_scheme,
+        futures::future::pending(),
+        futures::future::pending(),
+    )
+    .await
+    else {
+        unreachable!("Colour scheme superseded without newer colour schemes")
+    };
+    let result =
+        result.inspect(|()| remember_applied(backend, fingerprint, applied_schemes, color_scheme));
+    // Report a failure to apply the colour scheme in favour of a failure to
+    // shut down, but shut down in any case.
+    let shutdown_result = shutdown_backend(backend).await;
+    result.and(shutdown_result).map(|()| Outcome::Applied)
+}
+
+/// Apply `color_scheme` to all backends in `registry` once.
+///
+/// Initialize each backend, apply the colour scheme, and shut the backend down
+/// again.  Log errors just like the tasks created by [`spawn_backends`], and
+/// skip and remember applied colour schemes according to `options`; this
+/// function ignores the debounce delay of `options` though.
+///
+/// Return the name of each backend together with the result of applying the
+/// colour scheme, in the order backends were registered.
+pub async fn apply_once(
+    registry: &Registry,
+    color_scheme: ColorScheme,
+    options: &SpawnOptions,
+) -> Vec<(String, std::io::Result<Outcome>)> {
+    let backends_span = span!(Level::INFO, "backends").or_current();
+    let applies = registry.backends.iter().map(|registered| {
+        let backend = registered.backend.as_ref();
+        let span = span!(
+            parent: &backends_span,
+            Level::INFO,
+            "backend",
+            backend.name = backend.name()
+        );
+        apply_backend_once(registered, options, color_scheme)
+            .map(|result| (backend.name().to_owned(), result))
+            .instrument(span)
+    });
+    futures::future::join_all(applies).await
+}
+
+/// Diagnose all backends in `registry`.
+///
+/// Return the name of each backend together with the results of its checks,
+/// in the order backends were registered.
+pub async fn diagnose(registry: &Registry) -> Vec<(String, Vec<Diagnosis>)> {
+    let diagnoses = registry.backends.iter().map(|registered| {
+        let backend = registered.backend.as_ref();
+        backend
+            .diagnose()
+            .map(|diagnoses| (backend.name().to_owned(), diagnoses))
+    });
+    futures::future::join_all(diagnoses).await
+}
+
+/// A running backend in [`BackendTasks`].
+struct RunningBackend {
+    /// The task running the backend.
+    task: task::Id,
+    /// Asks the backend to stop.
+    stop_tx: watch::Sender<()>,
+    /// The fingerprint of the configuration of the backend.
+    fingerprint: String,
+}
+
+/// Tasks of running backends.
+///
+/// See [`spawn_backends`].
+pub struct BackendTasks {
+    tasks: JoinSet<std::io::Result<()>>,
+    /// Running backends by name.
+    running: HashMap<String, RunningBackend>,
+    /// Backend tasks we asked to stop, with the backend to start once the task
+    /// finished, if any.
+    stopping: HashMap<task::Id, Option<Registered>>,
+    /// Stopped backend tasks which failed to shut down or panicked.
+    failed_tasks: Vec<(task::Id, Box<dyn std::error::Error>)>,
+    color_scheme_rx: watch::Receiver<ColorScheme>,
+    applied_tx: mpsc::UnboundedSender<Applied>,
+    options: SpawnOptions,
+    debounce_tx: watch::Sender<Duration>,
+}
+
+/// Backends [`BackendTasks::update`] stopped and started.
+#[derive(Debug)]
+pub struct Changes {
+    /// Backends which were removed, or whose configuration changed.
+    pub stopped: Vec<String>,
+    /// Backends which were added, or whose configuration changed.
+    pub started: Vec<String>,
+}
+
+/// Record `result` of a finished backend task in `failed_tasks`, if it failed.
+fn record_failed_task(
+    result: Result<(task::Id, std::io::Result<()>), JoinError>,
+    failed_tasks: &mut Vec<(task::Id, Box<dyn std::error::Error>)>,
+) {
+    match result {
+        Err(error) if error.is_panic() => failed_tasks.push((error.id(), error.into())),
+        Ok((id, Err(error))) => failed_tasks.push((id, error.into())),
+        _ => {}
+    }
+}
+
+/// The ID of the task which produced `result`.
+fn task_id(result: &Result<(task::Id, std::io::Result<()>), JoinError>) -> task::Id {
+    match result {
+        Ok((id, _)) => *id,
+        Err(error) => error.id(),
+    }
+}
+
+impl BackendTasks {
+    /// Spawn a task for `registered`.
+    fn spawn(&mut self, registered: Registered) {
+        let backends_span = span!(Level::INFO, "backends").or_current();
+        let (stop_tx, stop_rx) = watch::channel(());
+        let name = registered.backend.name().to_owned();
+        let fingerprint = registered.fingerprint.clone();
+        let options = self.options.clone();
+        let color_scheme_rx = self.color_scheme_rx.clone();
+        let debounce_rx = self.debounce_tx.subscribe();
+        let applied_tx = self.applied_tx.clone();
+        let handle = self.tasks.spawn(async move {
+            let span = span!(
+                parent: &backends_span,
+                Level::INFO,
+                "backend",
+                backend.name = registered.backend.name(),
+                task.id = %tokio::task::id()
+            );
+            run_backend(
+                registered,
+                options,
+                color_scheme_rx,
+                stop_rx,
+                debounce_rx,
+                applied_tx,
+            )
+            .instrument(span)
+            .await
+        });
+        self.running.insert(
+            name,
+            RunningBackend {
+                task: handle.id(),
+                stop_tx,
+                fingerprint,
+            },
+        );
+    }
+
+    /// Wait until the next running backend task finishes.
+    ///
+    /// Collect the results of stopped backend tasks meanwhile, and start the
+    /// backends which wait for them, see [`BackendTasks::update`].
+    ///
+    /// Return `None` if no backend tasks remain.
+    pub async fn join_next_with_id(
+        &mut self,
+    ) -> Option<Result<(task::Id, std::io::Result<()>), JoinError>> {
+        loop {
+            let result = self.tasks.join_next_with_id().await?;
+            let id = task_id(&result);
+            if let Some(next) = self.stopping.remove(&id) {
+                record_failed_task(result, &mut self.failed_tasks);
+                if let Some(registered) = next {
+                    event!(
+                        Level::INFO,
+                        "Starting backend {}",
+                        registered.backend.name()
+                    );
+                    self.spawn(registered);
+                }
+            } else {
+                self.running.retain(|_, running| running.task != id);
+                return Some(result);
+            }
+        }
+    }
+
+    /// Update running backends to match `registry`.
+    ///
+    /// Ask backends which are not in `registry` anymore, or whose configuration
+    /// changed, to stop, but do not wait for them; [`BackendTasks::join_next_with_id`]
+    /// collects their tasks.  Start added backends right away, and changed
+    /// backends once their old task finished, to not have two tasks apply
+    /// colour schemes to the same backend.  Leave all other backends running,
+    /// and only update the debounce delay from `options` for them.
+    ///
+    /// Return the names of stopped and started backends.
+    pub fn update(&mut self, registry: &Registry, options: &SpawnOptions) -> Changes {
+        self.options = options.clone();
+        self.debounce_tx.send_replace(options.debounce);
+        let find = |name: &str| {
+            registry
+                .backends
+                .iter()
+                .find(|registered| registered.backend.name() == name)
+        };
+        let mut stopped = Vec::new();
+        // Backends waiting for their old task may have changed again.
+        for next in self.stopping.values_mut() {
+            if let Some(registered) = next.take() {
+                let name = registered.backend.name();
+                *next = find(name).cloned();
+                if next.is_none() {
+                    stopped.push(name.to_owned());
+                }
+            }
+        }
+        let mut waiting = self
+            .stopping
+            .values()
+            .flatten()
+            .map(|registered| registered.backend.name())
+            .collect::<HashSet<_>>();
+        let mut stopping = Vec::new();
+        self.running.retain(|name, running| {
+            let next = find(name);
+            if next.is_some_and(|registered| registered.fingerprint == running.fingerprint) {
+                return true;
+            }
+            event!(Level::INFO, "Stopping backend {name}");
+            running.stop_tx.send_replace(());
+            stopped.push(name.clone());
+            stopping.push((running.task, next.cloned()));
+            false
+        });
+        let mut started = stopping
+            .iter()
+            .flat_map(|(_, next)| next)
+            .map(|registered| registered.backend.name().to_owned())
+            .collect::<Vec<_>>();
+        waiting.extend(started.iter().map(String::as_str));
+        let added = registry
+            .backends
+            .iter()
+            .filter(|registered| {
+                let name = registered.backend.name();
+                !self.running.contains_key(name) && !waiting.contains(name)
             })
-            .instrument(span!(parent: &backends_span, Level::INFO, "backend.helix")),
-    );
+            .cloned()
+            .collect::<Vec<_>>();
+        self.stopping.extend(stopping);
+        for registered in added {
+            event!(
+                Level::INFO,
+                "Starting backend {}",
+                registered.backend.name()
+            );
+            started.push(registered.backend.name().to_owned());
+            self.spawn(registered);
+        }
+        Changes { stopped, started }
+    }
+
+    /// Stop all backends and wait for their tasks to finish.
+    ///
+    /// Backends finish applying the current colour scheme before they shut
+    /// down, but do not retry anymore; we never abort backend tasks.
+    ///
+    /// Return all backend tasks which failed to shut down or panicked,
+    /// including tasks stopped earlier by [`BackendTasks::update`].
+    pub async fn stop(mut self) -> Vec<(task::Id, Box<dyn std::error::Error>)> {
+        for running in self.running.values() {
+            running.stop_tx.send_replace(());
+        }
+        while let Some(result) = self.tasks.join_next_with_id().await {
+            record_failed_task(result, &mut self.failed_tasks);
+        }
+        self.failed_tasks
+    }
+}
+
+/// Options for running backends.
+#[derive(Debug, Clone, Default)]
+pub struct SpawnOptions {
+    /// How long the colour scheme must remain unchanged before backends apply it.
+    pub debounce: Duration,
+    /// Where to remember the colour schemes backends applied, if at all.
+    pub applied_schemes: Option<AppliedSchemes>,
+    /// Whether backends skip their first colour scheme if they applied it already.
+    pub skip_up_to_date: bool,
+}
+
+/// Spawn tasks for all backends in `registry`.
+///
+/// Provide the given colour scheme receiver to each backend to listen for
+/// colour scheme changes independently.  Each backend runs in its own task,
+/// within a span named after the backend.  Failures to apply a colour scheme
+/// are logged and retried according to the backend's apply configuration, but
+/// do not end the backend task; the task only fails if the backend fails to
+/// initialize or to shut down.  Each attempt to apply a colour scheme has a
+/// timeout; once a newer colour scheme arrives backends cancel the current
+/// attempt and stop retrying, so that the newest colour scheme always wins.
+///
+/// Backends wait until the colour scheme did not change for the debounce delay
+/// in `options`, and only apply the last colour scheme of a burst of rapid
+/// changes.  Backends remember every colour scheme they applied, and skip the
+/// first colour scheme if they applied it already, according to `options`.
+///
+/// Backends only see colour schemes which `color_scheme_rx` did not see yet;
+/// pass a receiver which never marks values as seen to make newly spawned
+/// backends apply the current colour scheme immediately.
+///
+/// Report every colour scheme a backend applied, or gave up on, to `applied_tx`.
+///
+/// Return the running backend tasks.
+pub fn spawn_backends(
+    registry: &Registry,
+    color_scheme_rx: &watch::Receiver<ColorScheme>,
+    applied_tx: &mpsc::UnboundedSender<Applied>,
+    options: &SpawnOptions,
+) -> BackendTasks {
+    let mut tasks = BackendTasks {
+        tasks: JoinSet::new(),
+        running: HashMap::new(),
+        stopping: HashMap::new(),
+        failed_tasks: Vec::new(),
+        color_scheme_rx: color_scheme_rx.clone(),
+        applied_tx: applied_tx.clone(),
+        options: options.clone(),
+        debounce_tx: watch::Sender::new(options.debounce),
+    };
+    for registered in &registry.backends {
+        tasks.spawn(registered.clone());
+    }
+    tasks
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use futures::{FutureExt, future::BoxFuture};
+    use tokio::{
+        sync::{mpsc, watch},
+        time::Instant,
+    };
+
+    use super::{
+        Applied, Attempt, Backend, ColorScheme, Registry, SpawnOptions, apply, apply_to_backend,
+        apply_with_retries, settle, spawn_backends,
+    };
+
+    /// What a [`FakeBackend`] does when applying a colour scheme.
+    #[derive(Debug, Clone, Copy)]
+    enum Behaviour {
+        /// Succeed after the given time.
+        Succeed(Duration),
+        /// Fail after the given time.
+        Fail(Duration),
+        /// Never finish.
+        Hang,
+    }
+
+    #[derive(Default)]
+    struct FakeState {
+        /// What to do for the next attempts; succeed immediately if empty.
+        script: VecDeque<Behaviour>,
+        /// The colour schemes of all attempts, when they started.
+        attempts: Vec<(ColorScheme, Instant)>,
+        /// The colour schemes of all finished attempts, with their result.
+        finished: Vec<(ColorScheme, bool)>,
+    }
+
+    /// A backend which applies colour schemes according to a script.
+    #[derive(Clone, Default)]
+    struct FakeBackend(Arc<Mutex<FakeState>>);
+
+    impl FakeBackend {
+        fn new(script: impl IntoIterator<Item = Behaviour>) -> Self {
+            let backend = Self::default();
+            backend.0.lock().unwrap().script.extend(script);
+            backend
+        }
+
+        fn attempts(&self) -> Vec<(ColorScheme, Instant)> {
+            self.0.lock().unwrap().attempts.clone()
+        }
+
+        fn finished(&self) -> Vec<(ColorScheme, bool)> {
+            self.0.lock().unwrap().finished.clone()
+        }
+    }
+
+    impl Backend for FakeBackend {
+        fn name(&self) -> &'static str {
+            "fake"
+        }
+
+        fn apply(&self, color_scheme: ColorScheme) -> BoxFuture<'_, std::io::Result<()>> {
+            let behaviour = {
+                let mut state = self.0.lock().unwrap();
+                state.attempts.push((color_scheme, Instant::now()));
+                state
+                    .script
+                    .pop_front()
+                    .unwrap_or(Behaviour::Succeed(Duration::ZERO))
+            };
+            async move {
+                let (delay, succeeded) = match behaviour {
+                    Behaviour::Succeed(delay) => (delay, true),
+                    Behaviour::Fail(delay) => (delay, false),
+                    Behaviour::Hang => futures::future::pending().await,
+                };
+                tokio::time::sleep(delay).await;
+                self.0
+                    .lock()
+                    .unwrap()
+                    .finished
+                    .push((color_scheme, succeeded));
+                if succeeded {
+                    Ok(())
+                } else {
+                    Err(std::io::Error::other("scripted failure"))
+                }
+            }
+            .boxed()
+        }
+    }
+
+    fn apply_config(retries: u32) -> apply::Config {
+        apply::Config {
+            timeout_ms: 60_000,
+            retries,
+            retry_delay_ms: 500,
+            max_retry_delay_ms: 10_000,
+        }
+    }
+
+    /// Channels for spawned backends.
+    struct Channels {
+        color_scheme_tx: watch::Sender<ColorScheme>,
+        applied_rx: mpsc::UnboundedReceiver<Applied>,
+    }
+
+    /// Spawn `backend` with `config` and a debounce delay of `debounce`.
+    fn spawn(
+        backend: &FakeBackend,
+        config: apply::Config,
+        debounce: Duration,
+    ) -> (super::BackendTasks, Channels) {
+        let mut registry = Registry::default();
+        registry.register(backend.clone(), &"fake", config);
+        let (color_scheme_tx, color_scheme_rx) = watch::channel(ColorScheme::NoPreference);
+        let (applied_tx, applied_rx) = mpsc::unbounded_channel();
+        let options = SpawnOptions {
+            debounce,
+            ..SpawnOptions::default()
+        };
+        let tasks = spawn_backends(&registry, &color_scheme_rx, &applied_tx, &options);
+        (
+            tasks,
+            Channels {
+                color_scheme_tx,
+                applied_rx,
+            },
+        )
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn newer_color_scheme_cancels_stale_apply() {
+        let backend = FakeBackend::new([
+            Behaviour::Succeed(Duration::from_secs(10)),
+            Behaviour::Succeed(Duration::from_secs(1)),
+        ]);
+        let (tasks, mut channels) = spawn(&backend, apply_config(3), Duration::ZERO);
+        channels
+            .color_scheme_tx
+            .send_replace(ColorScheme::PreferDark);
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        channels
+            .color_scheme_tx
+            .send_replace(ColorScheme::PreferLight);
+        let applied = channels.applied_rx.recv().await.unwrap();
+        assert_eq!(applied.color_scheme, ColorScheme::PreferLight);
+        assert!(applied.succeeded);
+        assert!(tasks.stop().await.is_empty());
+        // The stale apply never finished, and we never reported it
+        assert_eq!(backend.finished(), [(ColorScheme::PreferLight, true)]);
+        assert!(channels.applied_rx.try_recv().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn newer_color_scheme_stops_retries() {
+        let start = Instant::now();
+        let backend = FakeBackend::new([Behaviour::Fail(Duration::ZERO)]);
+        let superseded = tokio::time::sleep(Duration::from_millis(100));
+        let attempt = apply_with_retries(
+            &backend,
+            &apply_config(3),
+            ColorScheme::PreferDark,
+            superseded,
+            futures::future::pending(),
+        )
+        .await;
+        assert!(matches!(attempt, Attempt::Done(Err(_))));
+        assert_eq!(backend.attempts().len(), 1);
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stop_lets_current_apply_finish_without_retries() {
+        let backend = FakeBackend::new([
+            Behaviour::Fail(Duration::from_secs(5)),
+            Behaviour::Succeed(Duration::ZERO),
+        ]);
+        let (tasks, mut channels) = spawn(&backend, apply_config(3), Duration::ZERO);
+        channels
+            .color_scheme_tx
+            .send_replace(ColorScheme::PreferDark);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let start = Instant::now();
+        assert!(tasks.stop().await.is_empty());
+        assert_eq!(start.elapsed(), Duration::from_secs(4));
+        assert_eq!(backend.finished(), [(ColorScheme::PreferDark, false)]);
+        let applied = channels.applied_rx.recv().await.unwrap();
+        assert_eq!(applied.color_scheme, ColorScheme::PreferDark);
+        assert!(!applied.succeeded);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn give_up_after_retries() {
+        let start = Instant::now();
+        let backend = FakeBackend::new([Behaviour::Fail(Duration::ZERO); 4]);
+        let attempt = apply_with_retries(
+            &backend,
+            &apply_config(2),
+            ColorScheme::PreferDark,
+            futures::future::pending(),
+            futures::future::pending(),
+        )
+        .await;
+        assert!(matches!(attempt, Attempt::Done(Err(_))));
+        let attempts = backend
+            .attempts()
+            .into_iter()
+            .map(|(_, started)| started - start)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            attempts,
+            [
+                Duration::ZERO,
+                Duration::from_millis(500),
+                Duration::from_millis(1500)
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retry_until_success() {
+        let backend = FakeBackend::new([Behaviour::Fail(Duration::ZERO); 2]);
+        let (tasks, mut channels) = spawn(&backend, apply_config(3), Duration::ZERO);
+        channels
+            .color_scheme_tx
+            .send_replace(ColorScheme::PreferDark);
+        let applied = channels.applied_rx.recv().await.unwrap();
+        assert!(applied.succeeded);
+        assert_eq!(backend.attempts().len(), 3);
+        assert!(tasks.stop().await.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn debounce_applies_last_of_rapid_changes() {
+        let backend = FakeBackend::default();
+        let (tasks, mut channels) = spawn(&backend, apply_config(3), Duration::from_millis(100));
+        let start = Instant::now();
+        for color_scheme in [
+            ColorScheme::PreferDark,
+            ColorScheme::PreferLight,
+            ColorScheme::NoPreference,
+            ColorScheme::PreferDark,
+        ] {
+            channels.color_scheme_tx.send_replace(color_scheme);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        let applied = channels.applied_rx.recv().await.unwrap();
+        assert_eq!(applied.color_scheme, ColorScheme::PreferDark);
+        assert!(tasks.stop().await.is_empty());
+        assert!(channels.applied_rx.try_recv().is_err());
+        // The last change plus the debounce delay
+        assert_eq!(
+            backend.attempts(),
+            [(ColorScheme::PreferDark, start + Duration::from_millis(250))]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn zero_debounce_applies_immediately() {
+        let backend = FakeBackend::default();
+        let (tasks, mut channels) = spawn(&backend, apply_config(3), Duration::ZERO);
+        let start = Instant::now();
+        channels
+            .color_scheme_tx
+            .send_replace(ColorScheme::PreferDark);
+        let applied = channels.applied_rx.recv().await.unwrap();
+        assert_eq!(applied.color_scheme, ColorScheme::PreferDark);
+        assert_eq!(backend.attempts(), [(ColorScheme::PreferDark, start)]);
+        assert!(tasks.stop().await.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stop_ends_settle_promptly() {
+        let (color_scheme_tx, mut color_scheme_rx) = watch::channel(ColorScheme::NoPreference);
+        let (stop_tx, stop_rx) = watch::channel(());
+        let start = Instant::now();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            color_scheme_tx.send_replace(ColorScheme::PreferDark);
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            stop_tx.send_replace(());
+            // Keep the colour scheme sender around
+            futures::future::pending::<()>().await;
+        });
+        settle(&mut color_scheme_rx, &stop_rx, Duration::from_secs(3600)).await;
+        assert_eq!(start.elapsed(), Duration::from_secs(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn hanging_apply_times_out() {
+        let start = Instant::now();
+        let backend = FakeBackend::new([Behaviour::Hang]);
+        let config = apply::Config {
+            timeout_ms: 1000,
+            ..apply_config(0)
+        };
+        let error = apply_to_backend(&backend, &config, ColorScheme::PreferDark)
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+    }
 
-    backends
+    #[tokio::test(start_paused = true)]
+    async fn retry_after_timeout() {
+        let start = Instant::now();
+        let backend = FakeBackend::new([Behaviour::Hang]);
+        let config = apply::Config {
+            timeout_ms: 1000,
+            ..apply_config(1)
+        };
+        let attempt = apply_with_retries(
+            &backend,
+            &config,
+            ColorScheme::PreferDark,
+            futures::future::pending(),
+            futures::future::pending(),
+        )
+        .await;
+        assert!(matches!(attempt, Attempt::Done(Ok(()))));
+        assert_eq!(
+            backend.attempts(),
+            [
+                (ColorScheme::PreferDark, start),
+                (ColorScheme::PreferDark, start + Duration::from_millis(1500))
+            ]
+        );
+        assert_eq!(backend.finished(), [(ColorScheme::PreferDark, true)]);
+    }
 }
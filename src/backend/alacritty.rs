@@ -0,0 +1,218 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::path::PathBuf;
+
+use futures::{FutureExt, future::BoxFuture};
+use serde::Deserialize;
+use tracing::{Level, event};
+
+use crate::xdg;
+
+use super::{
+    Backend, ColorScheme, Diagnosis, apply, spawn_blocking_io,
+    theme::{self, ThemeNames},
+};
+
+/// Configuration for the Alacritty backend.
+///
+/// All themes are given by name, without the `.toml` extension.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Whether to enable the Alacritty backend.
+    pub enabled: bool,
+    /// The themes to use.
+    #[serde(flatten)]
+    pub themes: ThemeNames,
+    /// How to apply colour schemes.
+    pub apply: apply::Config,
+}
+
+fn alacritty_config_dir() -> PathBuf {
+    xdg::config_home().join("alacritty")
+}
+
+/// Apply the given [`ColorScheme`] to [Alacritty](https://alacritty.org/).
+///
+/// This function expects three themes to exist at `$XDG_CONFIG_HOME/alacritty`,
+/// named according to `config`; by default these are `darklightd-light.toml`,
+/// `darklightd-dark.toml`, and `darklightd-default.toml`, which also serves as
+/// fallback if either of the other themes is missing.
+///
+/// This function links the applicable variant to `darklightd-auto.toml` (or
+/// the configured auto theme) which can be imported in `alacritty.toml`.
+/// Alacritty watches imported files, and reloads its configuration whenever
+/// the link changes.
+///
+/// If `dry_run` is true only log the link, without changing anything.
+pub async fn apply_color_scheme(
+    config: &Config,
+    color_scheme: ColorScheme,
+    dry_run: bool,
+) -> std::io::Result<()> {
+    let files = config.themes.files(".toml");
+    spawn_blocking_io(move || {
+        theme::link_theme(
+            &alacritty_config_dir(),
+            &files,
+            color_scheme,
+            "alacritty",
+            dry_run,
+        )
+    })
+    .await
+    .inspect_err(|error| {
+        event!(Level::WARN, "Failed to update alacritty theme: {error}");
+    })
+}
+
+/// Whether the Alacritty configuration in `table` imports `file`.
+///
+/// Look at `general.import` and at the deprecated top-level `import`.
+fn imports_file(table: &toml::Table, file: &str) -> bool {
+    let general_import = table
+        .get("general")
+        .and_then(toml::Value::as_table)
+        .and_then(|general| general.get("import"));
+    [general_import, table.get("import")]
+        .into_iter()
+        .flatten()
+        .filter_map(toml::Value::as_array)
+        .flatten()
+        .filter_map(toml::Value::as_str)
+        .any(|import| import == file || import.ends_with(&format!("/{file}")))
+}
+
+fn diagnose_alacritty_config(config: &Config) -> Diagnosis {
+    let alacritty_config = alacritty_config_dir().join("alacritty.toml");
+    let auto_theme_file = config.themes.files(".toml").auto;
+    let fix = format!(
+        "Add 'import = [\"{}\"]' to the [general] table of {}",
+        alacritty_config_dir().join(&auto_theme_file).display(),
+        alacritty_config.display()
+    );
+    let contents = match std::fs::read_to_string(&alacritty_config) {
+        Ok(contents) => contents,
+        Err(error) => {
+            return Diagnosis::failed(
+                format!("Failed to read {}: {error}", alacritty_config.display()),
+                fix,
+            );
+        }
+    };
+    match contents.parse::<toml::Table>() {
+        Err(error) => Diagnosis::failed(
+            format!("Failed to parse {}: {error}", alacritty_config.display()),
+            format!("Fix the syntax of {}", alacritty_config.display()),
+        ),
+        Ok(table) if imports_file(&table, &auto_theme_file) => Diagnosis::Passed(format!(
+            "{} imports {auto_theme_file}",
+            alacritty_config.display()
+        )),
+        Ok(_) => Diagnosis::failed(
+            format!(
+                "{} does not import {auto_theme_file}",
+                alacritty_config.display()
+            ),
+            fix,
+        ),
+    }
+}
+
+/// Check whether Alacritty is set up for darklightd.
+///
+/// Check that all themes named in `config` exist, and that the Alacritty
+/// configuration imports the auto theme.
+pub fn diagnose(config: &Config) -> Vec<Diagnosis> {
+    let mut diagnoses = theme::diagnose_theme_files(
+        &alacritty_config_dir(),
+        &config.themes.files(".toml"),
+        "e.g. by copying a theme from https://github.com/alacritty/alacritty-theme",
+    );
+    diagnoses.push(diagnose_alacritty_config(config));
+    diagnoses
+}
+
+/// A backend which applies colour schemes to Alacritty.
+///
+/// See [`apply_color_scheme`].
+pub struct Alacritty {
+    config: Config,
+    dry_run: bool,
+}
+
+impl Alacritty {
+    /// Create a new Alacritty backend from `config`.
+    ///
+    /// If `dry_run` is true only log what the backend would do.
+    pub fn new(config: &Config, dry_run: bool) -> Self {
+        Self {
+            config: config.clone(),
+            dry_run,
+        }
+    }
+}
+
+impl Backend for Alacritty {
+    fn name(&self) -> &'static str {
+        "alacritty"
+    }
+
+    fn apply(&self, color_scheme: ColorScheme) -> BoxFuture<'_, std::io::Result<()>> {
+        apply_color_scheme(&self.config, color_scheme, self.dry_run).boxed()
+    }
+
+    fn diagnose(&self) -> BoxFuture<'_, Vec<Diagnosis>> {
+        futures::future::ready(diagnose(&self.config)).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::imports_file;
+
+    fn imports(config: &str, file: &str) -> bool {
+        imports_file(&toml::from_str(config).unwrap(), file)
+    }
+
+    #[test]
+    fn imports_file_in_general_import() {
+        let config = "[general]\nimport = [\"~/.config/alacritty/darklightd-auto.toml\"]\n";
+        assert!(imports(config, "darklightd-auto.toml"));
+        assert!(imports(
+            "[general]\nimport = [\"darklightd-auto.toml\"]\n",
+            "darklightd-auto.toml"
+        ));
+    }
+
+    #[test]
+    fn imports_file_in_deprecated_import() {
+        assert!(imports(
+            "import = [\"other.toml\", \"themes/darklightd-auto.toml\"]\n",
+            "darklightd-auto.toml"
+        ));
+    }
+
+    #[test]
+    fn imports_file_not_imported() {
+        assert!(!imports("", "darklightd-auto.toml"));
+        assert!(!imports(
+            "[general]\nimport = [\"other.toml\"]\n",
+            "darklightd-auto.toml"
+        ));
+        // Only whole file names match
+        assert!(!imports(
+            "[general]\nimport = [\"my-darklightd-auto.toml\"]\n",
+            "darklightd-auto.toml"
+        ));
+        // Imports must be arrays
+        assert!(!imports(
+            "[general]\nimport = \"darklightd-auto.toml\"\n",
+            "darklightd-auto.toml"
+        ));
+    }
+}
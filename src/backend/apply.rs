@@ -0,0 +1,149 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::time::Duration;
+
+use serde::Deserialize;
+
+/// How a backend applies colour schemes.
+///
+/// Each backend has its own apply configuration in the `apply` sub-table of
+/// the backend's table.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// How long to wait for the backend to apply a colour scheme, in milliseconds.
+    ///
+    /// If the backend takes longer darklightd cancels the attempt, and treats
+    /// it as failure.
+    pub timeout_ms: u64,
+    /// How often to retry applying a colour scheme if the backend failed.
+    pub retries: u32,
+    /// The delay before the first retry, in milliseconds.
+    ///
+    /// The delay doubles with every further retry.
+    pub retry_delay_ms: u64,
+    /// The maximum delay between retries, in milliseconds.
+    pub max_retry_delay_ms: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            timeout_ms: 10_000,
+            retries: 3,
+            retry_delay_ms: 500,
+            max_retry_delay_ms: 10_000,
+        }
+    }
+}
+
+impl Config {
+    /// The timeout for a single attempt to apply a colour scheme.
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+
+    /// The delays before each retry.
+    ///
+    /// Start with the retry delay and double it for every further retry, up
+    /// to the maximum retry delay.
+    pub fn retry_delays(&self) -> impl Iterator<Item = Duration> + use<> {
+        let max_delay = Duration::from_millis(self.max_retry_delay_ms);
+        std::iter::successors(Some(Duration::from_millis(self.retry_delay_ms)), |delay| {
+            Some(delay.saturating_mul(2))
+        })
+        .map(move |delay| delay.min(max_delay))
+        .take(usize::try_from(self.retries).unwrap_or(usize::MAX))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Config;
+
+    fn retry_delays_ms(config: &Config) -> Vec<u128> {
+        config
+            .retry_delays()
+            .map(|delay| delay.as_millis())
+            .collect()
+    }
+
+    #[test]
+    fn retry_delays_double() {
+        let config = Config {
+            retries: 5,
+            retry_delay_ms: 100,
+            max_retry_delay_ms: 10_000,
+            ..Config::default()
+        };
+        assert_eq!(retry_delays_ms(&config), [100, 200, 400, 800, 1600]);
+    }
+
+    #[test]
+    fn retry_delays_capped_at_max_retry_delay() {
+        let config = Config {
+            retries: 6,
+            retry_delay_ms: 500,
+            max_retry_delay_ms: 3000,
+            ..Config::default()
+        };
+        assert_eq!(
+            retry_delays_ms(&config),
+            [500, 1000, 2000, 3000, 3000, 3000]
+        );
+    }
+
+    #[test]
+    fn retry_delay_above_max_retry_delay() {
+        let config = Config {
+            retries: 2,
+            retry_delay_ms: 5000,
+            max_retry_delay_ms: 1000,
+            ..Config::default()
+        };
+        assert_eq!(retry_delays_ms(&config), [1000, 1000]);
+    }
+
+    #[test]
+    fn retry_delays_without_retries() {
+        let config = Config {
+            retries: 0,
+            ..Config::default()
+        };
+        assert_eq!(config.retry_delays().count(), 0);
+    }
+
+    #[test]
+    fn retry_delays_do_not_overflow() {
+        let config = Config {
+            retries: 200,
+            retry_delay_ms: u64::MAX,
+            max_retry_delay_ms: u64::MAX,
+            ..Config::default()
+        };
+        let delays = config.retry_delays().collect::<Vec<_>>();
+        assert_eq!(delays.len(), 200);
+        assert!(
+            delays
+                .iter()
+                .all(|delay| *delay == Duration::from_millis(u64::MAX))
+        );
+
+        let config = Config {
+            retries: 200,
+            retry_delay_ms: 1,
+            max_retry_delay_ms: u64::MAX,
+            ..Config::default()
+        };
+        let delays = config.retry_delays().collect::<Vec<_>>();
+        assert_eq!(delays.len(), 200);
+        assert_eq!(delays[10], Duration::from_millis(1024));
+        assert_eq!(delays[199], Duration::from_millis(u64::MAX));
+    }
+}
@@ -0,0 +1,217 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    io::ErrorKind,
+    os::unix::{fs::FileTypeExt, net::UnixStream},
+    path::{Path, PathBuf},
+};
+
+use futures::{FutureExt, future::BoxFuture};
+use serde::Deserialize;
+use tokio::process::Command;
+use tracing::{Level, event};
+
+use crate::xdg;
+
+use super::{
+    Backend, ColorScheme, Diagnosis, apply, diagnose_program, run_command, spawn_blocking_io,
+};
+
+/// Configuration for the Emacs backend.
+///
+/// All forms are Emacs Lisp forms, as accepted by `emacsclient --eval`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Whether to enable the Emacs backend.
+    pub enabled: bool,
+    /// The form to evaluate if the user has no preference.
+    pub default_form: String,
+    /// The form to evaluate if the user prefers light mode.
+    pub light_form: String,
+    /// The form to evaluate if the user prefers dark mode.
+    pub dark_form: String,
+    /// How to apply colour schemes.
+    pub apply: apply::Config,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let load_theme = |theme: &str| {
+            format!("(progn (mapc #'disable-theme custom-enabled-themes) (load-theme '{theme} t))")
+        };
+        Self {
+            enabled: false,
+            default_form: load_theme("modus-operandi"),
+            light_form: load_theme("modus-operandi"),
+            dark_form: load_theme("modus-vivendi"),
+            apply: apply::Config::default(),
+        }
+    }
+}
+
+impl Config {
+    /// The form to evaluate for `color_scheme`.
+    fn form_for(&self, color_scheme: ColorScheme) -> &str {
+        match color_scheme {
+            ColorScheme::NoPreference => &self.default_form,
+            ColorScheme::PreferDark => &self.dark_form,
+            ColorScheme::PreferLight => &self.light_form,
+        }
+    }
+}
+
+/// Whether a server listens on the socket at `path`.
+///
+/// Emacs leaves its socket behind if it crashes.
+fn is_listening(path: &Path) -> bool {
+    match UnixStream::connect(path) {
+        Ok(_) => true,
+        Err(error) => {
+            event!(
+                Level::DEBUG,
+                "Ignoring stale emacs socket {}: {error}",
+                path.display()
+            );
+            false
+        }
+    }
+}
+
+/// Find the server sockets of all running Emacs servers.
+///
+/// Look for sockets in `$XDG_RUNTIME_DIR/emacs`, where Emacs creates its
+/// server sockets, and ignore sockets no server listens on.
+fn find_servers() -> std::io::Result<Vec<PathBuf>> {
+    let server_dir = xdg::runtime_dir()
+        .ok_or_else(|| std::io::Error::new(ErrorKind::NotFound, "XDG_RUNTIME_DIR not set"))?
+        .join("emacs");
+    let mut servers = match std::fs::read_dir(server_dir) {
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_ok_and(|t| t.is_socket()))
+            .map(|entry| entry.path())
+            .filter(|path| is_listening(path))
+            .collect::<Vec<_>>(),
+        // Emacs creates the directory only when it starts the first server
+        Err(error) if error.kind() == ErrorKind::NotFound => Vec::new(),
+        Err(error) => return Err(error),
+    };
+    servers.sort_unstable();
+    Ok(servers)
+}
+
+/// Evaluate `form` in the Emacs server listening at `server`.
+async fn eval_form(server: &Path, form: &str, dry_run: bool) -> std::io::Result<()> {
+    let mut command = Command::new("emacsclient");
+    command
+        .arg("--socket-name")
+        .arg(server)
+        .args(["--eval", form]);
+    if dry_run {
+        event!(Level::INFO, "Would run {command:?}");
+        Ok(())
+    } else {
+        event!(Level::INFO, "Running {command:?}");
+        run_command(&mut command).await
+    }
+}
+
+/// Apply the given [`ColorScheme`] to [Emacs](https://www.gnu.org/software/emacs/).
+///
+/// Find all running Emacs servers in `$XDG_RUNTIME_DIR/emacs`, and evaluate
+/// the form for `color_scheme` from `config` in every server with
+/// `emacsclient`.  By default, this loads the Modus themes which come with
+/// Emacs.
+///
+/// Fail if evaluating the form failed in any server, but still try all other
+/// servers.
+///
+/// If `dry_run` is true only log the `emacsclient` commands instead of running
+/// them.
+pub async fn apply_color_scheme(
+    config: &Config,
+    color_scheme: ColorScheme,
+    dry_run: bool,
+) -> std::io::Result<()> {
+    let servers = spawn_blocking_io(find_servers).await.inspect_err(|error| {
+        event!(Level::WARN, "Failed to find emacs servers: {error}");
+    })?;
+    event!(Level::DEBUG, "Found emacs servers: {servers:?}");
+    let form = config.form_for(color_scheme);
+    let results = futures::future::join_all(
+        servers
+            .iter()
+            .map(|server| eval_form(server, form, dry_run)),
+    )
+    .await;
+    let mut first_error = None;
+    for (server, result) in servers.iter().zip(results) {
+        if let Err(error) = result {
+            event!(
+                Level::WARN,
+                "Failed to update emacs at {}: {error}",
+                server.display()
+            );
+            first_error.get_or_insert(error);
+        }
+    }
+    first_error.map_or(Ok(()), Err)
+}
+
+/// Check whether Emacs is set up for darklightd.
+///
+/// Check that `emacsclient` is in `$PATH`, and that we can find running
+/// servers.
+pub fn diagnose() -> Vec<Diagnosis> {
+    vec![
+        diagnose_program("emacsclient", "emacs"),
+        match find_servers() {
+            Ok(servers) => {
+                Diagnosis::Passed(format!("Found {} running emacs servers", servers.len()))
+            }
+            Err(error) => Diagnosis::failed(
+                format!("Failed to find running emacs servers: {error}"),
+                "Make sure that XDG_RUNTIME_DIR is set for darklightd and for emacs",
+            ),
+        },
+    ]
+}
+
+/// A backend which applies colour schemes to Emacs.
+///
+/// See [`apply_color_scheme`].
+pub struct Emacs {
+    config: Config,
+    dry_run: bool,
+}
+
+impl Emacs {
+    /// Create a new Emacs backend from `config`.
+    ///
+    /// If `dry_run` is true only log what the backend would do.
+    pub fn new(config: &Config, dry_run: bool) -> Self {
+        Self {
+            config: config.clone(),
+            dry_run,
+        }
+    }
+}
+
+impl Backend for Emacs {
+    fn name(&self) -> &'static str {
+        "emacs"
+    }
+
+    fn apply(&self, color_scheme: ColorScheme) -> BoxFuture<'_, std::io::Result<()>> {
+        apply_color_scheme(&self.config, color_scheme, self.dry_run).boxed()
+    }
+
+    fn diagnose(&self) -> BoxFuture<'_, Vec<Diagnosis>> {
+        futures::future::ready(diagnose()).boxed()
+    }
+}
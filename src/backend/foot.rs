@@ -0,0 +1,184 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{os::fd::OwnedFd, path::Path};
+
+use futures::{FutureExt, future::BoxFuture};
+use rustix::process::Signal;
+use serde::Deserialize;
+
+use crate::xdg;
+
+use super::{Backend, ColorScheme, Diagnosis, apply, diagnose_program, process};
+
+/// A colour palette of foot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Palette {
+    /// The palette in the `[colors]` section.
+    Colors,
+    /// The palette in the `[colors2]` section.
+    Colors2,
+}
+
+impl Palette {
+    /// The other palette.
+    fn other(self) -> Self {
+        match self {
+            Self::Colors => Self::Colors2,
+            Self::Colors2 => Self::Colors,
+        }
+    }
+
+    /// The signal which tells foot to switch to this palette.
+    fn signal(self) -> Signal {
+        match self {
+            Self::Colors => Signal::USR1,
+            Self::Colors2 => Signal::USR2,
+        }
+    }
+}
+
+/// Configuration for the foot backend.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Whether to enable the foot backend.
+    pub enabled: bool,
+    /// The palette to use if the user prefers dark mode.
+    ///
+    /// foot uses the other palette otherwise.
+    pub dark_palette: Palette,
+    /// How to apply colour schemes.
+    pub apply: apply::Config,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dark_palette: Palette::Colors,
+            apply: apply::Config::default(),
+        }
+    }
+}
+
+impl Config {
+    /// The palette to use for `color_scheme`.
+    fn palette(&self, color_scheme: ColorScheme) -> Palette {
+        match color_scheme {
+            ColorScheme::PreferDark => self.dark_palette,
+            ColorScheme::NoPreference | ColorScheme::PreferLight => self.dark_palette.other(),
+        }
+    }
+}
+
+fn is_foot_process(process: &OwnedFd) -> std::io::Result<bool> {
+    // Only match foot itself, and not footclient: foot serves all footclient
+    // windows, and footclient does not handle USR1 and USR2.
+    Ok(
+        process::executable_name(process)?.is_some_and(|name| name == "foot")
+            || Path::new(&process::argv0(process)?)
+                .file_name()
+                .is_some_and(|name| name == "foot"),
+    )
+}
+
+/// Apply the given [`ColorScheme`] to [foot](https://codeberg.org/dnkl/foot).
+///
+/// Iterate over all running processes, and send `SIGUSR1` or `SIGUSR2` to all
+/// processes whose executable or first command line field is named `foot`, to
+/// switch to the `[colors]` or `[colors2]` palette respectively.  This includes
+/// foot servers, and thus updates all windows of `footclient` as well.
+///
+/// Use the `dark-palette` from `config` for [`ColorScheme::PreferDark`], and
+/// the other palette otherwise.
+///
+/// If `dry_run` is true only log the processes to signal, without changing
+/// anything.
+pub async fn apply_color_scheme(
+    config: &Config,
+    color_scheme: ColorScheme,
+    dry_run: bool,
+) -> std::io::Result<()> {
+    let signal = config.palette(color_scheme).signal();
+    process::signal_matching_processes(is_foot_process, signal, "foot", dry_run)
+        .join_all()
+        .await;
+    Ok(())
+}
+
+fn diagnose_foot_config(config: &Config) -> Diagnosis {
+    let foot_config = xdg::config_home().join("foot").join("foot.ini");
+    let mode = match config.dark_palette {
+        Palette::Colors => "light",
+        Palette::Colors2 => "dark",
+    };
+    match std::fs::read_to_string(&foot_config) {
+        Ok(contents) if contents.lines().any(|line| line.trim() == "[colors2]") => {
+            Diagnosis::Passed(format!("{} has a [colors2] palette", foot_config.display()))
+        }
+        Ok(_) => Diagnosis::failed(
+            format!("{} has no [colors2] palette", foot_config.display()),
+            format!(
+                "Add a [colors2] section with colours for {mode} mode to {}",
+                foot_config.display()
+            ),
+        ),
+        Err(error) => Diagnosis::failed(
+            format!("Failed to read {}: {error}", foot_config.display()),
+            format!(
+                "Create {} with [colors] and [colors2] sections",
+                foot_config.display()
+            ),
+        ),
+    }
+}
+
+/// Check whether foot is set up for darklightd.
+///
+/// Check that foot is in `$PATH`, and that the foot configuration has a
+/// second palette to switch to.
+pub fn diagnose(config: &Config) -> Vec<Diagnosis> {
+    vec![
+        diagnose_program("foot", "foot"),
+        diagnose_foot_config(config),
+    ]
+}
+
+/// A backend which applies colour schemes to foot.
+///
+/// See [`apply_color_scheme`].
+pub struct Foot {
+    config: Config,
+    dry_run: bool,
+}
+
+impl Foot {
+    /// Create a new foot backend from `config`.
+    ///
+    /// If `dry_run` is true only log what the backend would do.
+    pub fn new(config: &Config, dry_run: bool) -> Self {
+        Self {
+            config: config.clone(),
+            dry_run,
+        }
+    }
+}
+
+impl Backend for Foot {
+    fn name(&self) -> &'static str {
+        "foot"
+    }
+
+    fn apply(&self, color_scheme: ColorScheme) -> BoxFuture<'_, std::io::Result<()>> {
+        apply_color_scheme(&self.config, color_scheme, self.dry_run).boxed()
+    }
+
+    fn diagnose(&self) -> BoxFuture<'_, Vec<Diagnosis>> {
+        futures::future::ready(diagnose(&self.config)).boxed()
+    }
+}
@@ -0,0 +1,229 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    os::fd::OwnedFd,
+    path::{Path, PathBuf},
+};
+
+use futures::{FutureExt, future::BoxFuture};
+use rustix::process::Signal;
+use serde::Deserialize;
+use tracing::{Level, event};
+
+use crate::xdg;
+
+use super::{
+    Backend, ColorScheme, Diagnosis, apply, diagnose_program, process, spawn_blocking_io,
+    theme::{self, ThemeNames},
+};
+
+/// Configuration for the Ghostty backend.
+///
+/// All themes are given by their file name.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Whether to enable the Ghostty backend.
+    pub enabled: bool,
+    /// The themes to use.
+    #[serde(flatten)]
+    pub themes: ThemeNames,
+    /// How to apply colour schemes.
+    pub apply: apply::Config,
+}
+
+fn ghostty_config_dir() -> PathBuf {
+    xdg::config_home().join("ghostty")
+}
+
+fn is_ghostty_process(process: &OwnedFd) -> std::io::Result<bool> {
+    Ok(
+        process::executable_name(process)?.is_some_and(|name| name == "ghostty")
+            || Path::new(&process::argv0(process)?)
+                .file_name()
+                .is_some_and(|name| name == "ghostty"),
+    )
+}
+
+/// Apply the given [`ColorScheme`] to [Ghostty](https://ghostty.org/).
+///
+/// This function expects three themes to exist at `$XDG_CONFIG_HOME/ghostty`,
+/// named according to `config`; by default these are `darklightd-light`,
+/// `darklightd-dark`, and `darklightd-default`, which also serves as fallback
+/// if either of the other themes is missing.
+///
+/// This function links the applicable variant to `darklightd-auto` (or the
+/// configured auto theme) which can be included in the Ghostty configuration
+/// with `config-file`.
+///
+/// After successfully updating the link, iterate over all running processes,
+/// and send `SIGUSR2` to all processes whose executable or first command line
+/// field is named `ghostty`, to tell Ghostty to reload its configuration.
+///
+/// If `dry_run` is true only log the link and the processes to signal, without
+/// changing anything.
+pub async fn apply_color_scheme(
+    config: &Config,
+    color_scheme: ColorScheme,
+    dry_run: bool,
+) -> std::io::Result<()> {
+    let files = config.themes.files("");
+    spawn_blocking_io(move || {
+        theme::link_theme(
+            &ghostty_config_dir(),
+            &files,
+            color_scheme,
+            "ghostty",
+            dry_run,
+        )
+    })
+    .await
+    .inspect_err(|error| {
+        event!(Level::WARN, "Failed to update ghostty theme: {error}");
+    })?;
+    process::signal_matching_processes(is_ghostty_process, Signal::USR2, "ghostty", dry_run)
+        .join_all()
+        .await;
+    Ok(())
+}
+
+/// Whether `line` of a Ghostty configuration includes `file`.
+fn includes_file(line: &str, file: &str) -> bool {
+    line.split_once('=').is_some_and(|(key, value)| {
+        let value = value.trim();
+        let value = value.strip_prefix('?').unwrap_or(value);
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .unwrap_or(value);
+        key.trim() == "config-file" && (value == file || value.ends_with(&format!("/{file}")))
+    })
+}
+
+fn diagnose_ghostty_config(config: &Config) -> Diagnosis {
+    // Ghostty prefers config.ghostty over the older config
+    let ghostty_config = ["config.ghostty", "config"]
+        .into_iter()
+        .map(|name| ghostty_config_dir().join(name))
+        .find(|path| path.exists())
+        .unwrap_or_else(|| ghostty_config_dir().join("config.ghostty"));
+    let fix = format!(
+        "Add 'config-file = {}' at the end of {}",
+        config.themes.auto_theme,
+        ghostty_config.display()
+    );
+    match std::fs::read_to_string(&ghostty_config) {
+        Ok(contents)
+            if contents
+                .lines()
+                .any(|line| includes_file(line, &config.themes.auto_theme)) =>
+        {
+            Diagnosis::Passed(format!(
+                "{} includes {}",
+                ghostty_config.display(),
+                config.themes.auto_theme
+            ))
+        }
+        Ok(_) => Diagnosis::failed(
+            format!(
+                "{} does not include {}",
+                ghostty_config.display(),
+                config.themes.auto_theme
+            ),
+            fix,
+        ),
+        Err(error) => Diagnosis::failed(
+            format!("Failed to read {}: {error}", ghostty_config.display()),
+            fix,
+        ),
+    }
+}
+
+/// Check whether Ghostty is set up for darklightd.
+///
+/// Check that Ghostty is in `$PATH`, that all themes named in `config` exist,
+/// and that the Ghostty configuration includes the auto theme.
+pub fn diagnose(config: &Config) -> Vec<Diagnosis> {
+    let mut diagnoses = vec![diagnose_program("ghostty", "ghostty")];
+    diagnoses.extend(theme::diagnose_theme_files(
+        &ghostty_config_dir(),
+        &config.themes.files(""),
+        "e.g. by copying a theme listed by 'ghostty +list-themes'",
+    ));
+    diagnoses.push(diagnose_ghostty_config(config));
+    diagnoses
+}
+
+/// A backend which applies colour schemes to Ghostty.
+///
+/// See [`apply_color_scheme`].
+pub struct Ghostty {
+    config: Config,
+    dry_run: bool,
+}
+
+impl Ghostty {
+    /// Create a new Ghostty backend from `config`.
+    ///
+    /// If `dry_run` is true only log what the backend would do.
+    pub fn new(config: &Config, dry_run: bool) -> Self {
+        Self {
+            config: config.clone(),
+            dry_run,
+        }
+    }
+}
+
+impl Backend for Ghostty {
+    fn name(&self) -> &'static str {
+        "ghostty"
+    }
+
+    fn apply(&self, color_scheme: ColorScheme) -> BoxFuture<'_, std::io::Result<()>> {
+        apply_color_scheme(&self.config, color_scheme, self.dry_run).boxed()
+    }
+
+    fn diagnose(&self) -> BoxFuture<'_, Vec<Diagnosis>> {
+        futures::future::ready(diagnose(&self.config)).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::includes_file;
+
+    #[test]
+    fn includes_file_matches() {
+        for line in [
+            "config-file = darklightd-auto",
+            "config-file=darklightd-auto",
+            "  config-file =  darklightd-auto  ",
+            "config-file = ?darklightd-auto",
+            "config-file = \"darklightd-auto\"",
+            "config-file = ?\"darklightd-auto\"",
+            "config-file = ~/.config/ghostty/darklightd-auto",
+            "config-file = themes/darklightd-auto",
+        ] {
+            assert!(includes_file(line, "darklightd-auto"), "{line}");
+        }
+    }
+
+    #[test]
+    fn includes_file_does_not_match() {
+        for line in [
+            "",
+            "config-file",
+            "config-file = other",
+            "config-file = my-darklightd-auto",
+            "config-file = darklightd-auto-light",
+            "theme = darklightd-auto",
+            "# config-file = darklightd-auto",
+        ] {
+            assert!(!includes_file(line, "darklightd-auto"), "{line}");
+        }
+    }
+}
@@ -4,49 +4,97 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use std::{io::ErrorKind, process::Stdio};
-
+use futures::{FutureExt, future::BoxFuture};
+use serde::Deserialize;
 use tokio::process::Command;
 use tracing::info;
 use zbus::zvariant;
 
-use super::ColorScheme;
+use super::{Backend, ColorScheme, Diagnosis, apply, diagnose_program, run_command};
 
 static GTK_THEME: &str = "/org/gnome/desktop/interface/gtk-theme";
 
+/// Configuration for the Gtk backend.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Whether to enable the Gtk backend.
+    pub enabled: bool,
+    /// The Gtk theme to use for dark mode.
+    pub dark_theme: String,
+    /// How to apply colour schemes.
+    pub apply: apply::Config,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            dark_theme: "Adwaita-dark".to_owned(),
+            apply: apply::Config::default(),
+        }
+    }
+}
+
 /// Apply the given colour scheme to Gtk.
 ///
 /// If `color_scheme` is [`ColorScheme::PreferDark`] change the `gtk-theme`
-/// key in the `org.gnome.desktop.interface` namespace to `Adwaita-dark`.
+/// key in the `org.gnome.desktop.interface` namespace to `dark_theme`.
 /// Otherwise reset the key to its default value.
-pub async fn apply_color_scheme(color_scheme: ColorScheme) -> std::io::Result<()> {
+///
+/// If `dry_run` is true only log the `dconf` command instead of running it.
+pub async fn apply_color_scheme(
+    dark_theme: &str,
+    color_scheme: ColorScheme,
+    dry_run: bool,
+) -> std::io::Result<()> {
     let mut command = Command::new("dconf");
     if let ColorScheme::PreferDark = color_scheme {
         command
             .args(["write", GTK_THEME])
             // dconf expects GVariant formatted arguments, and luckily zvariant is close enough
-            .arg(zvariant::Value::from("Adwaita-dark").to_string());
+            .arg(zvariant::Value::from(dark_theme).to_string());
     } else {
         command.args(["reset", GTK_THEME]);
     }
+    if dry_run {
+        info!("Would run {command:?} to apply color scheme {color_scheme:?} to Gtk");
+        return Ok(());
+    }
     info!("Running {command:?} to apply color scheme {color_scheme:?} to Gtk");
-    let output = command
-        .stdout(Stdio::null())
-        .stdin(Stdio::null())
-        .stderr(Stdio::piped())
-        .spawn()?
-        .wait_with_output()
-        .await?;
-    if output.status.success() {
-        Ok(())
-    } else {
-        Err(std::io::Error::new(
-            ErrorKind::Other,
-            format!(
-                "{command:?} failed with status {}: {}",
-                output.status,
-                String::from_utf8_lossy(&output.stderr)
-            ),
-        ))
+    run_command(&mut command).await
+}
+
+/// A backend which applies colour schemes to the legacy Gtk theme.
+///
+/// See [`apply_color_scheme`].
+pub struct Gtk {
+    dark_theme: String,
+    dry_run: bool,
+}
+
+impl Gtk {
+    /// Create a new Gtk backend from `config`.
+    ///
+    /// If `dry_run` is true only log what the backend would do.
+    pub fn new(config: &Config, dry_run: bool) -> Self {
+        Self {
+            dark_theme: config.dark_theme.clone(),
+            dry_run,
+        }
+    }
+}
+
+impl Backend for Gtk {
+    fn name(&self) -> &'static str {
+        "gtk"
+    }
+
+    fn apply(&self, color_scheme: ColorScheme) -> BoxFuture<'_, std::io::Result<()>> {
+        apply_color_scheme(&self.dark_theme, color_scheme, self.dry_run).boxed()
+    }
+
+    fn diagnose(&self) -> BoxFuture<'_, Vec<Diagnosis>> {
+        futures::future::ready(vec![diagnose_program("dconf", "dconf")]).boxed()
     }
 }
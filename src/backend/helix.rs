@@ -4,189 +4,184 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use std::{
-    ffi::OsString,
-    fs::{DirEntry, File, exists, rename},
-    io::{ErrorKind, Read},
-    os::{
-        fd::AsFd,
-        unix::{ffi::OsStringExt, fs::symlink},
-    },
-    path::{Path, PathBuf},
-};
+use std::os::fd::OwnedFd;
 
-use rustix::{
-    fs::{Mode, OFlags, openat, readlinkat},
-    process::{Signal, pidfd_send_signal},
-};
-use tokio::task::JoinSet;
+use futures::{FutureExt, future::BoxFuture};
+use rustix::process::Signal;
+use serde::Deserialize;
 use tracing::{Level, event};
 
 use crate::xdg;
 
-use super::ColorScheme;
-
-fn is_helix_process<F: AsFd>(process: F) -> std::io::Result<bool> {
-    // Check if the executable ends with helix or if helix is somewhere in cmdline[0]
-    let target = PathBuf::from(OsString::from_vec(
-        readlinkat(process.as_fd(), "exe", Vec::new())?.into_bytes(),
-    ));
-    if target
-        .file_name()
-        .and_then(|s| s.to_str())
-        .is_some_and(|s| s == "helix")
-    {
-        return Ok(true);
-    }
-
-    let mut source: File =
-        openat(process.as_fd(), "cmdline", OFlags::CLOEXEC, Mode::empty())?.into();
-    let mut cmdline = String::new();
-    source.read_to_string(&mut cmdline)?;
-    if let Some(argv0) = cmdline.split('\0').next() {
-        if argv0.contains("helix") {
-            return Ok(true);
-        }
-    }
-
-    Ok(false)
-}
+use super::{
+    Backend, ColorScheme, Diagnosis, apply, process, spawn_blocking_io,
+    theme::{self, ThemeNames},
+};
 
-fn process_dentry(dentry: &DirEntry) -> std::io::Result<()> {
-    let pidfd = rustix::fs::open(
-        dentry.path(),
-        OFlags::DIRECTORY | OFlags::CLOEXEC,
-        Mode::empty(),
-    )?;
-    if is_helix_process(&pidfd)? {
-        event!(
-            Level::INFO,
-            "Sending USR1 to presumed helix process {}",
-            dentry.file_name().to_string_lossy()
-        );
-        pidfd_send_signal(pidfd, Signal::USR1)?;
-    }
-    Ok(())
+/// Configuration for the Helix backend.
+///
+/// All themes are given by name, without the `.toml` extension.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Whether to enable the Helix backend.
+    pub enabled: bool,
+    /// The themes to use.
+    #[serde(flatten)]
+    pub themes: ThemeNames,
+    /// How to apply colour schemes.
+    pub apply: apply::Config,
 }
 
-fn update_all_helix_processes() -> JoinSet<()> {
-    let mut process_tasks = JoinSet::new();
-    match std::fs::read_dir("/proc") {
-        Err(error) => event!(Level::ERROR, "Failed to open /proc for reading: {error}"),
-        Ok(dentries) => {
-            for dentry in dentries.flatten() {
-                process_tasks.spawn_blocking(move || {
-                    if let Err(error) = process_dentry(&dentry) {
-                        match error.kind() {
-                            // Don't log if we've been looking at processes we
-                            // don't have permission to access, process that
-                            // we shortlived and vanished while we were looking
-                            // at them, and other non-directory things in /proc.
-                            ErrorKind::PermissionDenied
-                            | ErrorKind::NotFound
-                            | ErrorKind::NotADirectory => {}
-                            _ => {
-                                event!(
-                                    Level::DEBUG,
-                                    "Failed to handle dentry {}: {error}",
-                                    dentry.path().display()
-                                );
-                            }
-                        }
-                    }
-                });
-            }
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            themes: ThemeNames::default(),
+            apply: apply::Config::default(),
         }
     }
-    process_tasks
 }
 
-fn has_theme(theme_dir: &Path, filename: &str) -> bool {
-    exists(theme_dir.join(filename)).unwrap_or_default()
+fn is_helix_process(process: &OwnedFd) -> std::io::Result<bool> {
+    // Check if the executable ends with helix or if helix is somewhere in cmdline[0]
+    Ok(
+        process::executable_name(process)?.is_some_and(|name| name == "helix")
+            || process::argv0(process)?.contains("helix"),
+    )
 }
 
-fn update_theme_symlinks(color_scheme: ColorScheme) -> std::io::Result<()> {
+fn update_theme_symlinks(
+    config: &Config,
+    color_scheme: ColorScheme,
+    dry_run: bool,
+) -> std::io::Result<()> {
     let helix_themes_dir = xdg::config_home().join("helix").join("themes");
-    let default_theme = concat!(env!("CARGO_PKG_NAME"), "-default.toml");
-    let theme_filename = match color_scheme {
-        ColorScheme::NoPreference => default_theme,
-        ColorScheme::PreferDark => concat!(env!("CARGO_PKG_NAME"), "-dark.toml"),
-        ColorScheme::PreferLight => concat!(env!("CARGO_PKG_NAME"), "-light.toml"),
-    };
-
-    let theme_to_use = if has_theme(&helix_themes_dir, theme_filename) {
-        theme_filename
-    } else {
-        event!(
-            Level::DEBUG,
-            "Theme {theme_filename} does not exist, falling back to {default_theme}"
-        );
-        default_theme
-    };
-
-    if has_theme(&helix_themes_dir, theme_to_use) {
-        // Create a link at a temporary name and then rename it to -auto, to
-        // replace -auto atomically; otherwise there might be a brief window
-        // where -auto does not exist.
-        let auto_theme_name = concat!(env!("CARGO_PKG_NAME"), "-auto.toml");
-        let random_suffix = std::iter::from_fn(|| Some(fastrand::alphanumeric()))
-            .take(10)
-            .collect::<String>();
-        let temp_link = helix_themes_dir.join(format!(".{auto_theme_name}-{random_suffix}"));
-        let auto_theme_file = helix_themes_dir.join(auto_theme_name);
-        event!(
-            Level::DEBUG,
-            "Linking {theme_filename} at {}",
-            temp_link.display()
-        );
-        symlink(theme_to_use, &temp_link)?;
-        event!(
-            Level::INFO,
-            "Linking {theme_filename} at {} to apply {color_scheme:?} to helix",
-            auto_theme_file.display()
-        );
-        rename(&temp_link, auto_theme_file)
-    } else {
-        event!(
-            Level::WARN,
-            "None of {theme_filename} or {default_theme} exist in {}, not applying {color_scheme:?} to helix",
-            helix_themes_dir.display()
-        );
-        Err(std::io::Error::new(
-            ErrorKind::NotFound,
-            "No helix themes found for darklightd",
-        ))
-    }
+    theme::link_theme(
+        &helix_themes_dir,
+        &config.themes.files(".toml"),
+        color_scheme,
+        "helix",
+        dry_run,
+    )
 }
 
 /// Apply the given [`ColorScheme`] to [Helix](https://helix-editor.com/).
 ///
-/// This function expects three themes to exist at `$XDG_CONFIG_DIR/helix/themes`:
+/// This function expects three themes to exist at `$XDG_CONFIG_DIR/helix/themes`,
+/// named according to `config`; by default these are:
 ///
 /// - `darklightd-light.toml` for [`ColorScheme::PreferLight`]
 /// - `darklightd-dark.toml` for [`ColorScheme::PreferDark`]
 /// - `darklightd-default.toml`  for [`ColorScheme::NoPreference`] and as fallback if either of the other themes is missing.
 ///
 /// This function will then link the applicable variant to `darklight-auto.toml`
-/// which can be used as `theme` in the main `config.toml` of Helix.
+/// (or the configured auto theme) which can be used as `theme` in the main
+/// `config.toml` of Helix.
 ///
 /// After successfully updating the symlink, iterate over all running proesses,
 /// and send `SIGUSR1` to all processes whose executable is named `helix` or
 /// whose commandline has `helix` in its first field.  This attempts to tell
 /// running helix processes to reload their configuration.
-pub async fn apply_color_scheme(color_scheme: ColorScheme) -> std::io::Result<()> {
-    match tokio::task::spawn_blocking(move || update_theme_symlinks(color_scheme)).await {
-        Ok(Ok(())) => {
-            update_all_helix_processes().join_all().await;
-            Ok(())
-        }
-        Ok(Err(error)) => {
+///
+/// If `dry_run` is true only log the symlink and the processes to signal,
+/// without changing anything.
+pub async fn apply_color_scheme(
+    config: &Config,
+    color_scheme: ColorScheme,
+    dry_run: bool,
+) -> std::io::Result<()> {
+    let config = config.clone();
+    spawn_blocking_io(move || update_theme_symlinks(&config, color_scheme, dry_run))
+        .await
+        .inspect_err(|error| {
             event!(Level::WARN, "Failed to update helix theme: {error}");
-            Err(error)
-        }
+        })?;
+    process::signal_matching_processes(is_helix_process, Signal::USR1, "helix", dry_run)
+        .join_all()
+        .await;
+    Ok(())
+}
+
+fn diagnose_helix_config(config: &Config) -> Diagnosis {
+    let helix_config = xdg::config_home().join("helix").join("config.toml");
+    let fix = format!(
+        "Add 'theme = \"{}\"' to the top of {}",
+        config.themes.auto_theme,
+        helix_config.display()
+    );
+    let contents = match std::fs::read_to_string(&helix_config) {
+        Ok(contents) => contents,
         Err(error) => {
-            // SAFETY: We can't abort synchronous code, so a join error is always a panic from the sync task.
-            std::panic::resume_unwind(error.into_panic())
+            return Diagnosis::failed(
+                format!("Failed to read {}: {error}", helix_config.display()),
+                fix,
+            );
         }
+    };
+    match contents.parse::<toml::Table>() {
+        Err(error) => Diagnosis::failed(
+            format!("Failed to parse {}: {error}", helix_config.display()),
+            format!("Fix the syntax of {}", helix_config.display()),
+        ),
+        Ok(table) => match table.get("theme").and_then(toml::Value::as_str) {
+            Some(theme) if theme == config.themes.auto_theme => {
+                Diagnosis::Passed(format!("{} uses theme {theme}", helix_config.display()))
+            }
+            Some(theme) => Diagnosis::failed(
+                format!("{} uses theme {theme}", helix_config.display()),
+                fix,
+            ),
+            None => Diagnosis::failed(format!("{} sets no theme", helix_config.display()), fix),
+        },
+    }
+}
+
+/// Check whether Helix is set up for darklightd.
+///
+/// Check that all themes named in `config` exist, and that the Helix
+/// configuration uses the auto theme.
+pub fn diagnose(config: &Config) -> Vec<Diagnosis> {
+    let helix_themes_dir = xdg::config_home().join("helix").join("themes");
+    let mut diagnoses = theme::diagnose_theme_files(
+        &helix_themes_dir,
+        &config.themes.files(".toml"),
+        "e.g. with 'inherits = \"<some builtin theme>\"'",
+    );
+    diagnoses.push(diagnose_helix_config(config));
+    diagnoses
+}
+
+/// A backend which applies colour schemes to Helix.
+///
+/// See [`apply_color_scheme`].
+pub struct Helix {
+    config: Config,
+    dry_run: bool,
+}
+
+impl Helix {
+    /// Create a new Helix backend from `config`.
+    ///
+    /// If `dry_run` is true only log what the backend would do.
+    pub fn new(config: &Config, dry_run: bool) -> Self {
+        Self {
+            config: config.clone(),
+            dry_run,
+        }
+    }
+}
+
+impl Backend for Helix {
+    fn name(&self) -> &'static str {
+        "helix"
+    }
+
+    fn apply(&self, color_scheme: ColorScheme) -> BoxFuture<'_, std::io::Result<()>> {
+        apply_color_scheme(&self.config, color_scheme, self.dry_run).boxed()
+    }
+
+    fn diagnose(&self) -> BoxFuture<'_, Vec<Diagnosis>> {
+        futures::future::ready(diagnose(&self.config)).boxed()
     }
 }
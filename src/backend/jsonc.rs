@@ -0,0 +1,759 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Edit JSON with comments, as used by VS Code for its settings.
+//!
+//! We only look at the properties of objects, and edit the source text in
+//! place, to preserve comments and formatting.
+
+use std::{
+    fmt::{Display, Write},
+    io::ErrorKind,
+    ops::Range,
+    path::Path,
+};
+
+use tracing::{Level, event};
+
+use super::theme;
+
+/// A syntax error in JSON with comments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    /// The byte offset of the error in the source.
+    pub offset: usize,
+    /// What went wrong.
+    pub message: &'static str,
+}
+
+impl Display for SyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at byte {}", self.message, self.offset)
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
+impl From<SyntaxError> for std::io::Error {
+    fn from(error: SyntaxError) -> Self {
+        std::io::Error::new(ErrorKind::InvalidData, error)
+    }
+}
+
+/// A property of an object.
+#[derive(Debug)]
+struct Property {
+    /// The decoded key.
+    key: String,
+    /// The start of the quoted key in the source.
+    key_start: usize,
+    /// The range of the value in the source.
+    value: Range<usize>,
+    /// The position of the comma after the value, if any.
+    comma: Option<usize>,
+}
+
+/// An object.
+#[derive(Debug)]
+struct Object {
+    /// The position of the opening brace.
+    open: usize,
+    /// The position of the closing brace.
+    close: usize,
+    /// All properties, in source order.
+    properties: Vec<Property>,
+}
+
+struct Scanner<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(source: &'a str) -> Self {
+        Self {
+            bytes: source.as_bytes(),
+            position: 0,
+        }
+    }
+
+    fn error(&self, message: &'static str) -> SyntaxError {
+        SyntaxError {
+            offset: self.position,
+            message,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.position).copied()
+    }
+
+    fn expect(&mut self, byte: u8, message: &'static str) -> Result<(), SyntaxError> {
+        if self.peek() == Some(byte) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(self.error(message))
+        }
+    }
+
+    /// Skip whitespace and comments.
+    fn skip_trivia(&mut self) -> Result<(), SyntaxError> {
+        loop {
+            match (self.peek(), self.bytes.get(self.position + 1)) {
+                (Some(b' ' | b'\t' | b'\n' | b'\r'), _) => self.position += 1,
+                (Some(b'/'), Some(b'/')) => {
+                    while self.peek().is_some_and(|b| b != b'\n') {
+                        self.position += 1;
+                    }
+                }
+                (Some(b'/'), Some(b'*')) => {
+                    let start = self.position;
+                    self.position += 2;
+                    loop {
+                        match self.peek() {
+                            None => {
+                                self.position = start;
+                                return Err(self.error("Unterminated comment"));
+                            }
+                            Some(b'*') if self.bytes.get(self.position + 1) == Some(&b'/') => {
+                                self.position += 2;
+                                break;
+                            }
+                            Some(_) => self.position += 1,
+                        }
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn scan_hex4(&mut self) -> Result<u32, SyntaxError> {
+        let digits = self
+            .bytes
+            .get(self.position..self.position + 4)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| self.error("Invalid unicode escape"))?;
+        self.position += 4;
+        Ok(digits)
+    }
+
+    /// Scan a string and return its decoded contents.
+    fn scan_string(&mut self) -> Result<String, SyntaxError> {
+        self.expect(b'"', "Expected string")?;
+        let mut decoded = Vec::new();
+        loop {
+            match self.peek() {
+                None | Some(b'\n') => return Err(self.error("Unterminated string")),
+                Some(b'"') => {
+                    self.position += 1;
+                    // We copy all bytes of multi-byte characters, so this is lossless
+                    return Ok(String::from_utf8_lossy(&decoded).into_owned());
+                }
+                Some(b'\\') => {
+                    self.position += 1;
+                    let escaped = self
+                        .peek()
+                        .ok_or_else(|| self.error("Unterminated string"))?;
+                    self.position += 1;
+                    let c = match escaped {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut code = self.scan_hex4()?;
+                            if (0xd800..0xdc00).contains(&code)
+                                && self.bytes.get(self.position..self.position + 2) == Some(b"\\u")
+                            {
+                                self.position += 2;
+                                let low = self.scan_hex4()?;
+                                if !(0xdc00..0xe000).contains(&low) {
+                                    return Err(self.error("Invalid surrogate pair"));
+                                }
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+                        }
+                        _ => return Err(self.error("Invalid escape in string")),
+                    };
+                    let mut buffer = [0; 4];
+                    decoded.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                }
+                Some(byte) => {
+                    decoded.push(byte);
+                    self.position += 1;
+                }
+            }
+        }
+    }
+
+    /// Skip over a value of any type.
+    fn skip_value(&mut self) -> Result<(), SyntaxError> {
+        match self.peek() {
+            Some(b'"') => self.scan_string().map(|_| ()),
+            Some(open @ (b'{' | b'[')) => {
+                let close = if open == b'{' { b'}' } else { b']' };
+                self.position += 1;
+                loop {
+                    self.skip_trivia()?;
+                    if self.peek() == Some(close) {
+                        self.position += 1;
+                        return Ok(());
+                    }
+                    if open == b'{' {
+                        self.scan_string()?;
+                        self.skip_trivia()?;
+                        self.expect(b':', "Expected colon")?;
+                        self.skip_trivia()?;
+                    }
+                    self.skip_value()?;
+                    self.skip_trivia()?;
+                    match self.peek() {
+                        Some(b',') => self.position += 1,
+                        Some(byte) if byte == close => {}
+                        _ => return Err(self.error("Expected comma")),
+                    }
+                }
+            }
+            _ => {
+                // Numbers, true, false, and null
+                let start = self.position;
+                while self
+                    .peek()
+                    .is_some_and(|b| b.is_ascii_alphanumeric() || b"+-.".contains(&b))
+                {
+                    self.position += 1;
+                }
+                if self.position == start {
+                    Err(self.error("Expected value"))
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    /// Parse the object at the current position.
+    fn parse_object(&mut self) -> Result<Object, SyntaxError> {
+        let open = self.position;
+        self.expect(b'{', "Expected object")?;
+        let mut properties = Vec::new();
+        loop {
+            self.skip_trivia()?;
+            if self.peek() == Some(b'}') {
+                break;
+            }
+            let key_start = self.position;
+            let key = self.scan_string()?;
+            self.skip_trivia()?;
+            self.expect(b':', "Expected colon")?;
+            self.skip_trivia()?;
+            let value_start = self.position;
+            self.skip_value()?;
+            let value = value_start..self.position;
+            self.skip_trivia()?;
+            let comma = match self.peek() {
+                Some(b',') => {
+                    self.position += 1;
+                    Some(self.position - 1)
+                }
+                Some(b'}') => None,
+                _ => return Err(self.error("Expected comma")),
+            };
+            properties.push(Property {
+                key,
+                key_start,
+                value,
+                comma,
+            });
+        }
+        let close = self.position;
+        self.position += 1;
+        Ok(Object {
+            open,
+            close,
+            properties,
+        })
+    }
+
+    /// Parse the whole source as a single top-level object.
+    fn parse_document(&mut self) -> Result<Object, SyntaxError> {
+        self.skip_trivia()?;
+        let object = self.parse_object()?;
+        self.skip_trivia()?;
+        if self.peek().is_some() {
+            return Err(self.error("Unexpected content after object"));
+        }
+        Ok(object)
+    }
+}
+
+impl Object {
+    /// Find the property `key`.
+    ///
+    /// If the object has the property more than once, return the last one,
+    /// because it takes precedence.
+    fn find(&self, key: &str) -> Option<&Property> {
+        self.properties
+            .iter()
+            .rev()
+            .find(|property| property.key == key)
+    }
+}
+
+/// Parse the object whose value starts at `position` in `source`.
+///
+/// Return `None` if the value at `position` is not an object.
+fn parse_object_at(source: &str, position: usize) -> Result<Option<Object>, SyntaxError> {
+    if source.as_bytes().get(position) == Some(&b'{') {
+        let mut scanner = Scanner::new(source);
+        scanner.position = position;
+        scanner.parse_object().map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+/// Quote `s` as a JSON string.
+fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => write!(quoted, "\\u{:04x}", u32::from(c)).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// The leading whitespace of the line containing `position`.
+fn line_indentation(source: &str, position: usize) -> &str {
+    let line_start = source[..position].rfind('\n').map_or(0, |index| index + 1);
+    let line = &source[line_start..];
+    let end = line
+        .find(|c: char| c != ' ' && c != '\t')
+        .unwrap_or(line.len());
+    &line[..end]
+}
+
+/// The indentation of a single level in `source`.
+///
+/// Use the indentation of the first indented line, or four spaces if no line
+/// is indented.
+fn indentation_unit(source: &str) -> &str {
+    source
+        .lines()
+        .map(|line| {
+            let end = line
+                .find(|c: char| c != ' ' && c != '\t')
+                .unwrap_or(line.len());
+            (&line[..end], end < line.len())
+        })
+        .find(|(indentation, has_content)| !indentation.is_empty() && *has_content)
+        .map_or("    ", |(indentation, _)| indentation)
+}
+
+/// Whether only whitespace precedes `position` on its line.
+fn starts_line(source: &str, position: usize) -> bool {
+    let line_start = source[..position].rfind('\n').map_or(0, |index| index + 1);
+    source[line_start..position]
+        .bytes()
+        .all(|b| b == b' ' || b == b'\t')
+}
+
+/// The end of all comments which follow `position` on its line.
+///
+/// Skip whitespace and comments after `position`, up to the end of its line,
+/// including block comments which start on the line but end on a later line.
+/// Return the position after the last comment, or `position` if no comment
+/// follows on the same line.
+fn end_of_trailing_comments(source: &str, position: usize) -> usize {
+    let bytes = source.as_bytes();
+    let mut end = position;
+    let mut current = position;
+    loop {
+        while matches!(bytes.get(current), Some(b' ' | b'\t')) {
+            current += 1;
+        }
+        match bytes.get(current..current + 2) {
+            Some(b"//") => {
+                return source[current..]
+                    .find(['\r', '\n'])
+                    .map_or(source.len(), |index| current + index);
+            }
+            Some(b"/*") => match source[current + 2..].find("*/") {
+                Some(index) => {
+                    current += index + 4;
+                    end = current;
+                }
+                None => return end,
+            },
+            _ => return end,
+        }
+    }
+}
+
+/// Format `value` nested in objects with `keys`, from outermost to innermost.
+fn nested_value(keys: &[&str], value: &str) -> String {
+    match keys.split_first() {
+        None => quote(value),
+        Some((key, rest)) => format!("{{ {}: {} }}", quote(key), nested_value(rest, value)),
+    }
+}
+
+/// Insert `property_text` as the last property of `object` in `source`.
+fn insert_property(source: &str, object: &Object, property_text: &str) -> String {
+    let mut edited = source.to_owned();
+    let outer_indentation = line_indentation(source, object.open);
+    let inner_indentation = format!("{outer_indentation}{}", indentation_unit(source));
+    if let Some(last) = object.properties.last() {
+        let indentation = if starts_line(source, last.key_start) {
+            line_indentation(source, last.key_start)
+        } else {
+            &inner_indentation
+        };
+        // Insert after the last property, but keep comments after the last
+        // property with it.
+        let insert_at =
+            end_of_trailing_comments(source, last.comma.map_or(last.value.end, |comma| comma + 1));
+        let trailing_comma = if last.comma.is_some() { "," } else { "" };
+        edited.insert_str(
+            insert_at,
+            &format!("\n{indentation}{property_text}{trailing_comma}"),
+        );
+        if last.comma.is_none() {
+            edited.insert(last.value.end, ',');
+        }
+    } else if source[object.open + 1..object.close].trim().is_empty() {
+        edited.replace_range(
+            object.open..=object.close,
+            &format!("{{\n{inner_indentation}{property_text}\n{outer_indentation}}}"),
+        );
+    } else {
+        // Keep comments in an otherwise empty object
+        edited.insert_str(
+            object.open + 1,
+            &format!("\n{inner_indentation}{property_text}"),
+        );
+    }
+    edited
+}
+
+/// Get the source text of the value at `path`.
+///
+/// `path` lists the keys of nested objects, starting at the top-level object.
+/// Return `None` if there is no value at `path`.
+pub fn get<'a>(source: &'a str, path: &[&str]) -> Result<Option<&'a str>, SyntaxError> {
+    if source.trim().is_empty() {
+        return Ok(None);
+    }
+    let mut object = Scanner::new(source).parse_document()?;
+    let mut remaining = path;
+    while let Some((key, rest)) = remaining.split_first() {
+        let Some(property) = object.find(key) else {
+            return Ok(None);
+        };
+        if rest.is_empty() {
+            return Ok(Some(&source[property.value.clone()]));
+        }
+        match parse_object_at(source, property.value.start)? {
+            Some(nested) => object = nested,
+            None => return Ok(None),
+        }
+        remaining = rest;
+    }
+    Ok(None)
+}
+
+/// Set the value at `path` to the string `value`.
+///
+/// `path` lists the keys of nested objects, starting at the top-level object.
+/// Replace the value at `path` if it exists, otherwise add the missing
+/// properties at the end of their objects, and replace values in `path` which
+/// are no objects.  Keep all other source text, including comments and
+/// formatting, as is.
+///
+/// Treat empty `source` as an empty object.
+///
+/// # Panics
+///
+/// Panic if `path` is empty.
+pub fn set_string(source: &str, path: &[&str], value: &str) -> Result<String, SyntaxError> {
+    assert!(!path.is_empty(), "Empty path");
+    if source.trim().is_empty() {
+        return set_string("{}\n", path, value);
+    }
+    let mut object = Scanner::new(source).parse_document()?;
+    let mut remaining = path;
+    while let Some((key, rest)) = remaining.split_first() {
+        let Some(property) = object.find(key) else {
+            let property_text = format!("{}: {}", quote(key), nested_value(rest, value));
+            return Ok(insert_property(source, &object, &property_text));
+        };
+        let nested = if rest.is_empty() {
+            None
+        } else {
+            parse_object_at(source, property.value.start)?
+        };
+        let Some(nested) = nested else {
+            let mut edited = source.to_owned();
+            edited.replace_range(property.value.clone(), &nested_value(rest, value));
+            return Ok(edited);
+        };
+        object = nested;
+        remaining = rest;
+    }
+    unreachable!("path is not empty")
+}
+
+/// Edit the file at `path` with `edit`.
+///
+/// Resolve symlinks to edit the actual file, and treat a missing file as
+/// empty.  Only write the file if `edit` changed its contents, and write it
+/// atomically with the permissions of the original file, see
+/// [`theme::atomic_write`].  Use `change` in log messages.
+///
+/// If `dry_run` is true only log the change, without writing anything.
+pub fn edit_file<F>(path: &Path, change: &str, dry_run: bool, edit: F) -> std::io::Result<()>
+where
+    F: FnOnce(&str) -> Result<String, SyntaxError>,
+{
+    let path = match std::fs::canonicalize(path) {
+        Ok(target) => target,
+        Err(error) if error.kind() == ErrorKind::NotFound => path.to_owned(),
+        Err(error) => return Err(error),
+    };
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == ErrorKind::NotFound => String::new(),
+        Err(error) => return Err(error),
+    };
+    let edited = edit(&contents)?;
+    if edited == contents {
+        event!(
+            Level::DEBUG,
+            "{} already up to date: {change}",
+            path.display()
+        );
+    } else if dry_run {
+        event!(Level::INFO, "Would {change} in {}", path.display());
+    } else {
+        event!(Level::INFO, "Editing {} to {change}", path.display());
+        if let Some(directory) = path.parent() {
+            std::fs::create_dir_all(directory)?;
+        }
+        theme::atomic_write(&path, &edited)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SyntaxError, get, set_string};
+
+    #[test]
+    fn set_string_in_empty_source() {
+        assert_eq!(
+            set_string("", &["a"], "x").unwrap(),
+            "{\n    \"a\": \"x\"\n}\n"
+        );
+        assert_eq!(
+            set_string(" \n", &["a"], "x").unwrap(),
+            "{\n    \"a\": \"x\"\n}\n"
+        );
+    }
+
+    #[test]
+    fn set_string_in_empty_object() {
+        assert_eq!(
+            set_string("{}", &["a"], "x").unwrap(),
+            "{\n    \"a\": \"x\"\n}"
+        );
+    }
+
+    #[test]
+    fn set_string_replaces_existing_key() {
+        let source = "{\n  // Theme\n  \"a\": \"old\", // keep\n  \"b\": 1\n}\n";
+        assert_eq!(
+            set_string(source, &["a"], "new").unwrap(),
+            "{\n  // Theme\n  \"a\": \"new\", // keep\n  \"b\": 1\n}\n"
+        );
+        assert_eq!(
+            set_string(source, &["b"], "x\"y").unwrap(),
+            "{\n  // Theme\n  \"a\": \"old\", // keep\n  \"b\": \"x\\\"y\"\n}\n"
+        );
+    }
+
+    #[test]
+    fn set_string_appends_without_trailing_comma() {
+        assert_eq!(
+            set_string("{\n  \"a\": 1\n}\n", &["b"], "x").unwrap(),
+            "{\n  \"a\": 1,\n  \"b\": \"x\"\n}\n"
+        );
+        assert_eq!(
+            set_string("{\"a\":1,\"b\":2}", &["c"], "x").unwrap(),
+            "{\"a\":1,\"b\":2,\n    \"c\": \"x\"}"
+        );
+    }
+
+    #[test]
+    fn set_string_appends_with_trailing_comma() {
+        assert_eq!(
+            set_string("{\n\t\"a\": [1, 2,],\n}\n", &["b"], "x").unwrap(),
+            "{\n\t\"a\": [1, 2,],\n\t\"b\": \"x\",\n}\n"
+        );
+    }
+
+    #[test]
+    fn set_string_keeps_line_comment_after_last_property() {
+        assert_eq!(
+            set_string("{\n  \"a\": 1 // c\n}\n", &["b"], "x").unwrap(),
+            "{\n  \"a\": 1, // c\n  \"b\": \"x\"\n}\n"
+        );
+        assert_eq!(
+            set_string("{\n  \"a\": 1, // c\n}\n", &["b"], "x").unwrap(),
+            "{\n  \"a\": 1, // c\n  \"b\": \"x\",\n}\n"
+        );
+    }
+
+    #[test]
+    fn set_string_keeps_block_comments_after_last_property() {
+        assert_eq!(
+            set_string("{\n  \"a\": 1 /* x */ // y\n}\n", &["b"], "x").unwrap(),
+            "{\n  \"a\": 1, /* x */ // y\n  \"b\": \"x\"\n}\n"
+        );
+        assert_eq!(
+            set_string("{\n  \"a\": 1 /* x\n  */\n}\n", &["b"], "x").unwrap(),
+            "{\n  \"a\": 1, /* x\n  */\n  \"b\": \"x\"\n}\n"
+        );
+    }
+
+    #[test]
+    fn set_string_keeps_comments_on_following_lines() {
+        assert_eq!(
+            set_string("{\n  \"a\": 1\n  // end\n}\n", &["b"], "x").unwrap(),
+            "{\n  \"a\": 1,\n  \"b\": \"x\"\n  // end\n}\n"
+        );
+    }
+
+    #[test]
+    fn set_string_in_object_with_only_comments() {
+        assert_eq!(
+            set_string("{\n  // only comment\n}\n", &["b"], "x").unwrap(),
+            "{\n  \"b\": \"x\"\n  // only comment\n}\n"
+        );
+    }
+
+    #[test]
+    fn set_string_in_nested_objects() {
+        let source = "{\n  \"theme\": {\n    \"mode\": \"system\",\n    \"light\": \"A\"\n  }\n}\n";
+        assert_eq!(
+            set_string(source, &["theme", "mode"], "dark").unwrap(),
+            "{\n  \"theme\": {\n    \"mode\": \"dark\",\n    \"light\": \"A\"\n  }\n}\n"
+        );
+        assert_eq!(
+            set_string(
+                "{\n  \"theme\": {\"light\": \"A\"}\n}\n",
+                &["theme", "mode"],
+                "dark"
+            )
+            .unwrap(),
+            "{\n  \"theme\": {\"light\": \"A\",\n    \"mode\": \"dark\"}\n}\n"
+        );
+        assert_eq!(
+            set_string("{\n  \"a\": 1\n}\n", &["b", "c"], "x").unwrap(),
+            "{\n  \"a\": 1,\n  \"b\": { \"c\": \"x\" }\n}\n"
+        );
+        assert_eq!(
+            set_string("{\n  \"b\": \"old\"\n}\n", &["b", "c"], "x").unwrap(),
+            "{\n  \"b\": { \"c\": \"x\" }\n}\n"
+        );
+    }
+
+    #[test]
+    fn get_nested_values() {
+        let source = "// Settings\n{\n  \"theme\": { \"mode\": \"dark\" },\n  \"a\": [1, {}],\n}\n";
+        assert_eq!(get(source, &["theme", "mode"]).unwrap(), Some("\"dark\""));
+        assert_eq!(
+            get(source, &["theme"]).unwrap(),
+            Some("{ \"mode\": \"dark\" }")
+        );
+        assert_eq!(get(source, &["a"]).unwrap(), Some("[1, {}]"));
+        assert_eq!(get(source, &["a", "b"]).unwrap(), None);
+        assert_eq!(get(source, &["missing"]).unwrap(), None);
+        assert_eq!(get("", &["a"]).unwrap(), None);
+    }
+
+    #[test]
+    fn duplicate_keys_use_last_property() {
+        let source = "{\n  \"a\": \"first\",\n  \"a\": \"second\"\n}\n";
+        assert_eq!(get(source, &["a"]).unwrap(), Some("\"second\""));
+        assert_eq!(
+            set_string(source, &["a"], "x").unwrap(),
+            "{\n  \"a\": \"first\",\n  \"a\": \"x\"\n}\n"
+        );
+    }
+
+    #[test]
+    fn syntax_errors_have_offsets() {
+        let error = |source: &str| set_string(source, &["a"], "x").unwrap_err();
+        assert_eq!(
+            error("{\"a\" 1}"),
+            SyntaxError {
+                offset: 5,
+                message: "Expected colon"
+            }
+        );
+        assert_eq!(
+            error("{\"a\": 1 \"b\": 2}"),
+            SyntaxError {
+                offset: 8,
+                message: "Expected comma"
+            }
+        );
+        assert_eq!(
+            error("{\"a\": \"x}"),
+            SyntaxError {
+                offset: 9,
+                message: "Unterminated string"
+            }
+        );
+        assert_eq!(
+            error("{} /* x"),
+            SyntaxError {
+                offset: 3,
+                message: "Unterminated comment"
+            }
+        );
+        assert_eq!(
+            error("{} {}"),
+            SyntaxError {
+                offset: 3,
+                message: "Unexpected content after object"
+            }
+        );
+        assert_eq!(
+            error("[]"),
+            SyntaxError {
+                offset: 0,
+                message: "Expected object"
+            }
+        );
+    }
+}
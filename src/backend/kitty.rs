@@ -0,0 +1,421 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use futures::{FutureExt, future::BoxFuture};
+use serde::Deserialize;
+use tokio::process::Command;
+use tracing::{Level, event};
+
+use crate::xdg;
+
+use super::{
+    Backend, ColorScheme, Diagnosis, apply, diagnose_program, run_command, spawn_blocking_io,
+    theme::{self, ThemeNames},
+};
+
+/// Configuration for the kitty backend.
+///
+/// All themes are given by name, without the `.conf` extension.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Whether to enable the kitty backend.
+    pub enabled: bool,
+    /// The themes to use.
+    #[serde(flatten)]
+    pub themes: ThemeNames,
+    /// The `listen_on` setting of kitty.
+    ///
+    /// Used to find the remote control sockets of all running kitty instances.
+    pub listen_on: String,
+    /// How to apply colour schemes.
+    pub apply: apply::Config,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            themes: ThemeNames::default(),
+            listen_on: "unix:@kitty".to_owned(),
+            apply: apply::Config::default(),
+        }
+    }
+}
+
+fn kitty_config_dir() -> PathBuf {
+    xdg::config_home().join("kitty")
+}
+
+/// Expand `$NAME` and `${NAME}` in `s` with environment variables.
+///
+/// Expand unset variables to the empty string, like kitty does.
+fn expand_env(s: &str) -> String {
+    let mut expanded = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(index) = rest.find('$') {
+        expanded.push_str(&rest[..index]);
+        rest = &rest[index + 1..];
+        let (name, remainder) = if let Some(braced) = rest.strip_prefix('{') {
+            braced.split_once('}').unwrap_or((braced, ""))
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            rest.split_at(end)
+        };
+        if name.is_empty() {
+            expanded.push('$');
+        } else if let Some(value) = std::env::var_os(name) {
+            expanded.push_str(&value.to_string_lossy());
+        }
+        rest = remainder;
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// The remote control sockets of running kitty instances.
+///
+/// kitty replaces `{kitty_pid}` in `listen_on` with its process ID, or appends
+/// `-` and its process ID to `listen_on` if `listen_on` has no `{kitty_pid}`.
+#[derive(Debug)]
+struct SocketPattern {
+    /// The directory containing the sockets, or `None` for abstract sockets.
+    directory: Option<PathBuf>,
+    /// The part of the socket name before the process ID.
+    prefix: String,
+    /// The part of the socket name after the process ID.
+    suffix: String,
+}
+
+impl SocketPattern {
+    /// Parse the kitty `listen_on` setting into a socket pattern.
+    ///
+    /// Fail if `listen_on` does not denote a UNIX socket.
+    fn parse(listen_on: &str) -> std::io::Result<Self> {
+        let Some(address) = expand_env(listen_on)
+            .strip_prefix("unix:")
+            .map(ToOwned::to_owned)
+        else {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unsupported listen_on {listen_on}, only unix: sockets are supported"),
+            ));
+        };
+        let (prefix, suffix) = match address.split_once("{kitty_pid}") {
+            Some((prefix, suffix)) => (prefix.to_owned(), suffix.to_owned()),
+            None => (format!("{address}-"), String::new()),
+        };
+        if prefix.starts_with('@') {
+            Ok(Self {
+                directory: None,
+                prefix,
+                suffix,
+            })
+        } else {
+            let path = Path::new(&prefix);
+            let directory = path.parent().unwrap_or(Path::new("/")).to_owned();
+            let prefix = path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned();
+            Ok(Self {
+                directory: Some(directory),
+                prefix,
+                suffix,
+            })
+        }
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        name.strip_prefix(&self.prefix)
+            .and_then(|name| name.strip_suffix(&self.suffix))
+            .is_some_and(|pid| !pid.is_empty() && pid.bytes().all(|b| b.is_ascii_digit()))
+    }
+
+    /// Find the addresses of all sockets matching this pattern.
+    ///
+    /// Look for abstract sockets in `/proc/net/unix`, and for other sockets in
+    /// the socket directory.  Return addresses suitable for `kitty @ --to`.
+    fn find_sockets(&self) -> std::io::Result<Vec<String>> {
+        let mut addresses = match &self.directory {
+            None => std::fs::read_to_string("/proc/net/unix")?
+                .lines()
+                // The path is the eighth column, and only present for bound sockets
+                .filter_map(|line| line.split_whitespace().nth(7))
+                .filter(|name| self.matches(name))
+                .map(|name| format!("unix:{name}"))
+                .collect::<Vec<_>>(),
+            Some(directory) => std::fs::read_dir(directory)?
+                .filter_map(Result::ok)
+                .filter(|entry| self.matches(&entry.file_name().to_string_lossy()))
+                .map(|entry| format!("unix:{}", entry.path().display()))
+                .collect(),
+        };
+        // /proc/net/unix lists a listening socket once for every connection
+        addresses.sort_unstable();
+        addresses.dedup();
+        Ok(addresses)
+    }
+}
+
+/// Tell the kitty instance listening on `address` to load colours from `colors_file`.
+async fn set_colors(address: &str, colors_file: &Path, dry_run: bool) -> std::io::Result<()> {
+    let mut command = Command::new("kitty");
+    command
+        .args(["@", "--to", address, "set-colors", "--all", "--configured"])
+        .arg(colors_file);
+    if dry_run {
+        event!(Level::INFO, "Would run {command:?}");
+        Ok(())
+    } else {
+        event!(Level::INFO, "Running {command:?}");
+        run_command(&mut command).await
+    }
+}
+
+/// Apply the given [`ColorScheme`] to [kitty](https://sw.kovidgoyal.net/kitty/).
+///
+/// This function expects three themes to exist at `$XDG_CONFIG_HOME/kitty`,
+/// named according to `config`; by default these are `darklightd-light.conf`,
+/// `darklightd-dark.conf`, and `darklightd-default.conf`, which also serves as
+/// fallback if either of the other themes is missing.
+///
+/// This function links the applicable variant to `darklightd-auto.conf` (or the
+/// configured auto theme) which can be included in `kitty.conf`.
+///
+/// After updating the link, find the remote control sockets of all running
+/// kitty instances according to the `listen-on` setting in `config`, and tell
+/// each instance to load the new colours with `kitty @ set-colors`.  Log but
+/// otherwise ignore failures to update a single kitty instance, since kitty
+/// does not always clean up stale sockets.
+///
+/// If `dry_run` is true only log the link and the commands, without changing
+/// anything.
+pub async fn apply_color_scheme(
+    config: &Config,
+    color_scheme: ColorScheme,
+    dry_run: bool,
+) -> std::io::Result<()> {
+    let pattern = SocketPattern::parse(&config.listen_on)?;
+    let files = config.themes.files(".conf");
+    let auto_theme_file = kitty_config_dir().join(&files.auto);
+    let addresses = spawn_blocking_io(move || {
+        theme::link_theme(&kitty_config_dir(), &files, color_scheme, "kitty", dry_run)?;
+        pattern.find_sockets()
+    })
+    .await
+    .inspect_err(|error| {
+        event!(Level::WARN, "Failed to update kitty theme: {error}");
+    })?;
+    event!(
+        Level::DEBUG,
+        "Found kitty remote control sockets: {addresses:?}"
+    );
+    for address in addresses {
+        if let Err(error) = set_colors(&address, &auto_theme_file, dry_run).await {
+            event!(
+                Level::WARN,
+                "Failed to update colours of kitty at {address}: {error}"
+            );
+        }
+    }
+    Ok(())
+}
+
+fn diagnose_kitty_config(config: &Config) -> Diagnosis {
+    let kitty_config = kitty_config_dir().join("kitty.conf");
+    let auto_theme_file = config.themes.files(".conf").auto;
+    let include = format!("include {auto_theme_file}");
+    let fix = format!(
+        "Add '{include}' at the end of {}, and 'allow_remote_control socket-only' and 'listen_on {}' to it",
+        kitty_config.display(),
+        config.listen_on
+    );
+    match std::fs::read_to_string(&kitty_config) {
+        Ok(contents) if contents.lines().any(|line| line.trim() == include) => Diagnosis::Passed(
+            format!("{} includes {auto_theme_file}", kitty_config.display()),
+        ),
+        Ok(_) => Diagnosis::failed(
+            format!(
+                "{} does not include {auto_theme_file}",
+                kitty_config.display()
+            ),
+            fix,
+        ),
+        Err(error) => Diagnosis::failed(
+            format!("Failed to read {}: {error}", kitty_config.display()),
+            fix,
+        ),
+    }
+}
+
+/// Check whether kitty is set up for darklightd.
+///
+/// Check that kitty is in `$PATH`, that all themes named in `config` exist,
+/// that the kitty configuration includes the auto theme, and that we
+/// understand `listen-on`.
+pub fn diagnose(config: &Config) -> Vec<Diagnosis> {
+    let mut diagnoses = vec![diagnose_program("kitty", "kitty")];
+    diagnoses.extend(theme::diagnose_theme_files(
+        &kitty_config_dir(),
+        &config.themes.files(".conf"),
+        "e.g. by copying a theme from https://github.com/kovidgoyal/kitty-themes",
+    ));
+    diagnoses.push(diagnose_kitty_config(config));
+    diagnoses.push(
+        match SocketPattern::parse(&config.listen_on).and_then(|p| p.find_sockets()) {
+            Ok(addresses) => Diagnosis::Passed(format!(
+                "Found {} running kitty instances listening on {}",
+                addresses.len(),
+                config.listen_on
+            )),
+            Err(error) => Diagnosis::failed(
+                format!(
+                    "Failed to find kitty instances listening on {}: {error}",
+                    config.listen_on
+                ),
+                "Set listen-on in the [kitty] table to the listen_on setting of kitty, e.g. unix:@kitty",
+            ),
+        },
+    );
+    diagnoses
+}
+
+/// A backend which applies colour schemes to kitty.
+///
+/// See [`apply_color_scheme`].
+pub struct Kitty {
+    config: Config,
+    dry_run: bool,
+}
+
+impl Kitty {
+    /// Create a new kitty backend from `config`.
+    ///
+    /// If `dry_run` is true only log what the backend would do.
+    pub fn new(config: &Config, dry_run: bool) -> Self {
+        Self {
+            config: config.clone(),
+            dry_run,
+        }
+    }
+}
+
+impl Backend for Kitty {
+    fn name(&self) -> &'static str {
+        "kitty"
+    }
+
+    fn apply(&self, color_scheme: ColorScheme) -> BoxFuture<'_, std::io::Result<()>> {
+        apply_color_scheme(&self.config, color_scheme, self.dry_run).boxed()
+    }
+
+    fn diagnose(&self) -> BoxFuture<'_, Vec<Diagnosis>> {
+        futures::future::ready(diagnose(&self.config)).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{SocketPattern, expand_env};
+
+    /// The value of the environment variable `name`, or the empty string.
+    fn env(name: &str) -> String {
+        std::env::var_os(name)
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn expand_env_variables() {
+        let path = env("PATH");
+        assert_eq!(expand_env("$PATH"), path);
+        assert_eq!(expand_env("${PATH}"), path);
+        assert_eq!(expand_env("a/$PATH/b"), format!("a/{path}/b"));
+        assert_eq!(expand_env("a${PATH}b"), format!("a{path}b"));
+        assert_eq!(expand_env("no variables"), "no variables");
+    }
+
+    #[test]
+    fn expand_env_unset_variables() {
+        assert_eq!(expand_env("a-$DARKLIGHTD_TEST_UNSET-b"), "a--b");
+        assert_eq!(expand_env("a-${DARKLIGHTD_TEST_UNSET}-b"), "a--b");
+    }
+
+    #[test]
+    fn expand_env_without_name() {
+        assert_eq!(expand_env("a$"), "a$");
+        assert_eq!(expand_env("a$-b"), "a$-b");
+        assert_eq!(expand_env("a${}b"), "a$b");
+    }
+
+    #[test]
+    fn parse_abstract_socket() {
+        let pattern = SocketPattern::parse("unix:@mykitty-{kitty_pid}.sock").unwrap();
+        assert_eq!(pattern.directory, None);
+        assert_eq!(pattern.prefix, "@mykitty-");
+        assert_eq!(pattern.suffix, ".sock");
+        assert!(pattern.matches("@mykitty-1234.sock"));
+        assert!(!pattern.matches("@mykitty-.sock"));
+        assert!(!pattern.matches("@mykitty-12a4.sock"));
+        assert!(!pattern.matches("@mykitty-1234"));
+        assert!(!pattern.matches("@other-1234.sock"));
+    }
+
+    #[test]
+    fn parse_path_socket() {
+        let pattern = SocketPattern::parse("unix:/run/user/1000/kitty-{kitty_pid}").unwrap();
+        assert_eq!(
+            pattern.directory.as_deref(),
+            Some(Path::new("/run/user/1000"))
+        );
+        assert_eq!(pattern.prefix, "kitty-");
+        assert_eq!(pattern.suffix, "");
+        assert!(pattern.matches("kitty-42"));
+        assert!(!pattern.matches("kitty-"));
+        assert!(!pattern.matches("/run/user/1000/kitty-42"));
+    }
+
+    #[test]
+    fn parse_without_kitty_pid() {
+        let pattern = SocketPattern::parse("unix:@mykitty").unwrap();
+        assert_eq!(pattern.directory, None);
+        assert_eq!(pattern.prefix, "@mykitty-");
+        assert_eq!(pattern.suffix, "");
+        assert!(pattern.matches("@mykitty-1234"));
+        assert!(!pattern.matches("@mykitty"));
+
+        let pattern = SocketPattern::parse("unix:/tmp/mykitty").unwrap();
+        assert_eq!(pattern.directory.as_deref(), Some(Path::new("/tmp")));
+        assert_eq!(pattern.prefix, "mykitty-");
+        assert!(pattern.matches("mykitty-1234"));
+    }
+
+    #[test]
+    fn parse_expands_env() {
+        let pattern =
+            SocketPattern::parse("unix:${DARKLIGHTD_TEST_UNSET}/tmp/kitty-{kitty_pid}").unwrap();
+        assert_eq!(pattern.directory.as_deref(), Some(Path::new("/tmp")));
+        assert_eq!(pattern.prefix, "kitty-");
+    }
+
+    #[test]
+    fn parse_unsupported_address() {
+        let error = SocketPattern::parse("tcp:localhost:12345").unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+    }
+}
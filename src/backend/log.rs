@@ -0,0 +1,44 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use futures::{FutureExt, future::BoxFuture};
+use serde::Deserialize;
+use tracing::{Level, event};
+
+use super::{Backend, ColorScheme, apply};
+
+/// Configuration for the log backend.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Whether to log colour scheme changes.
+    pub enabled: bool,
+    /// How to apply colour schemes.
+    pub apply: apply::Config,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            apply: apply::Config::default(),
+        }
+    }
+}
+
+/// A backend which just logs colour scheme changes.
+pub struct Log;
+
+impl Backend for Log {
+    fn name(&self) -> &'static str {
+        "log"
+    }
+
+    fn apply(&self, color_scheme: ColorScheme) -> BoxFuture<'_, std::io::Result<()>> {
+        event!(Level::INFO, "Color scheme updated to {color_scheme:?}");
+        futures::future::ok(()).boxed()
+    }
+}
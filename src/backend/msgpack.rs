@@ -0,0 +1,517 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A minimal [MessagePack](https://msgpack.org/) encoder and decoder.
+//!
+//! Supports just what we need to talk msgpack-RPC to other processes: we
+//! encode and decode nil, booleans, integers, strings, and arrays, and skip
+//! over values of all other types when decoding.
+
+use std::io::ErrorKind;
+
+/// A MessagePack value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    /// The nil value.
+    Nil,
+    /// A boolean.
+    Boolean(bool),
+    /// An integer.
+    ///
+    /// We do not support unsigned integers beyond [`i64::MAX`].
+    Integer(i64),
+    /// A UTF-8 string.
+    String(String),
+    /// An array of values.
+    Array(Vec<Value>),
+    /// A value of any other type, as encoded bytes.
+    Other(Vec<u8>),
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_owned())
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Self::Integer(value)
+    }
+}
+
+impl Value {
+    /// Get the integer of this value, if it is an integer.
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Self::Integer(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Get the string of this value, if it is a string.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Get the items of this value, if it is an array.
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Self::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    /// Encode this value and append the encoded bytes to `buffer`.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn encode(&self, buffer: &mut Vec<u8>) {
+        match self {
+            Self::Nil => buffer.push(0xc0),
+            Self::Boolean(false) => buffer.push(0xc2),
+            Self::Boolean(true) => buffer.push(0xc3),
+            // Positive and negative fixint
+            Self::Integer(value @ (-32..=0x7f)) => buffer.push(*value as u8),
+            Self::Integer(value @ 0..) => {
+                buffer.push(0xcf);
+                buffer.extend_from_slice(&(*value as u64).to_be_bytes());
+            }
+            Self::Integer(value) => {
+                buffer.push(0xd3);
+                buffer.extend_from_slice(&value.to_be_bytes());
+            }
+            Self::String(value) => {
+                let length = value.len();
+                if length < 32 {
+                    buffer.push(0xa0 | length as u8);
+                } else {
+                    encode_length(buffer, length, Some(0xd9), 0xda, 0xdb);
+                }
+                buffer.extend_from_slice(value.as_bytes());
+            }
+            Self::Array(items) => {
+                let length = items.len();
+                if length < 16 {
+                    buffer.push(0x90 | length as u8);
+                } else {
+                    encode_length(buffer, length, None, 0xdc, 0xdd);
+                }
+                for item in items {
+                    item.encode(buffer);
+                }
+            }
+            Self::Other(bytes) => buffer.extend_from_slice(bytes),
+        }
+    }
+
+    /// Decode a single value from the start of `buffer`.
+    ///
+    /// Return the value and the number of bytes it took, or `None` if
+    /// `buffer` does not contain a complete value yet.  Fail with
+    /// [`ErrorKind::InvalidData`] if `buffer` does not start with a valid
+    /// value.
+    pub fn decode(buffer: &[u8]) -> std::io::Result<Option<(Self, usize)>> {
+        let mut reader = Reader {
+            buffer,
+            position: 0,
+        };
+        match reader.read_value() {
+            Ok(value) => Ok(Some((value, reader.position))),
+            Err(DecodeError::Incomplete) => Ok(None),
+            Err(DecodeError::Invalid(message)) => {
+                Err(std::io::Error::new(ErrorKind::InvalidData, message))
+            }
+        }
+    }
+}
+
+/// Encode a `length` with the marker for 8, 16, or 32 bit lengths.
+///
+/// Arrays have no `marker8`.
+#[allow(clippy::cast_possible_truncation)]
+fn encode_length(
+    buffer: &mut Vec<u8>,
+    length: usize,
+    marker8: Option<u8>,
+    marker16: u8,
+    marker32: u8,
+) {
+    match (marker8, u8::try_from(length), u16::try_from(length)) {
+        (Some(marker8), Ok(length), _) => {
+            buffer.push(marker8);
+            buffer.push(length);
+        }
+        (_, _, Ok(length)) => {
+            buffer.push(marker16);
+            buffer.extend_from_slice(&length.to_be_bytes());
+        }
+        _ => {
+            buffer.push(marker32);
+            buffer.extend_from_slice(&(length as u32).to_be_bytes());
+        }
+    }
+}
+
+enum DecodeError {
+    /// The buffer ended before the end of the value.
+    Incomplete,
+    /// The buffer contained invalid data.
+    Invalid(&'static str),
+}
+
+struct Reader<'a> {
+    buffer: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, length: usize) -> Result<&[u8], DecodeError> {
+        let end = self
+            .position
+            .checked_add(length)
+            .ok_or(DecodeError::Invalid("length overflow"))?;
+        let bytes = self
+            .buffer
+            .get(self.position..end)
+            .ok_or(DecodeError::Incomplete)?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn read_length(&mut self, size: usize) -> Result<usize, DecodeError> {
+        let length = match size {
+            1 => u32::from(self.read_u8()?),
+            2 => u32::from(u16::from_be_bytes(self.take_array()?)),
+            _ => u32::from_be_bytes(self.take_array()?),
+        };
+        usize::try_from(length).map_err(|_| DecodeError::Invalid("length too large"))
+    }
+
+    fn read_string(&mut self, length: usize) -> Result<Value, DecodeError> {
+        let bytes = self.take(length)?;
+        String::from_utf8(bytes.to_vec())
+            .map(Value::String)
+            .map_err(|_| DecodeError::Invalid("invalid UTF-8 in string"))
+    }
+
+    fn read_array(&mut self, length: usize) -> Result<Value, DecodeError> {
+        // Don't trust the length for preallocation
+        let mut items = Vec::new();
+        for _ in 0..length {
+            items.push(self.read_value()?);
+        }
+        Ok(Value::Array(items))
+    }
+
+    /// Skip `count` values.
+    fn skip_values(&mut self, count: usize) -> Result<(), DecodeError> {
+        for _ in 0..count {
+            self.read_value()?;
+        }
+        Ok(())
+    }
+
+    /// Skip a map with `length` entries.
+    fn skip_map(&mut self, length: usize) -> Result<(), DecodeError> {
+        self.skip_values(
+            length
+                .checked_mul(2)
+                .ok_or(DecodeError::Invalid("length overflow"))?,
+        )
+    }
+
+    /// Skip an extension with `length` bytes of data, after its type tag.
+    fn skip_extension(&mut self, length: usize) -> Result<(), DecodeError> {
+        self.take(1)?;
+        self.take(length)?;
+        Ok(())
+    }
+
+    /// Get all bytes since `start` as a value of another type.
+    fn other(&self, start: usize) -> Value {
+        Value::Other(self.buffer[start..self.position].to_vec())
+    }
+
+    #[allow(clippy::cast_possible_wrap)]
+    fn read_value(&mut self) -> Result<Value, DecodeError> {
+        let start = self.position;
+        let marker = self.read_u8()?;
+        match marker {
+            0x00..=0x7f => Ok(Value::Integer(i64::from(marker))),
+            0x90..=0x9f => self.read_array(usize::from(marker & 0x0f)),
+            0xa0..=0xbf => self.read_string(usize::from(marker & 0x1f)),
+            0xc0 => Ok(Value::Nil),
+            0xc1 => Err(DecodeError::Invalid("reserved marker 0xc1")),
+            0xc2 => Ok(Value::Boolean(false)),
+            0xc3 => Ok(Value::Boolean(true)),
+            0xcc => Ok(Value::Integer(i64::from(self.read_u8()?))),
+            0xcd => Ok(Value::Integer(i64::from(u16::from_be_bytes(
+                self.take_array()?,
+            )))),
+            0xce => Ok(Value::Integer(i64::from(u32::from_be_bytes(
+                self.take_array()?,
+            )))),
+            0xcf => i64::try_from(u64::from_be_bytes(self.take_array()?))
+                .map(Value::Integer)
+                .map_err(|_| DecodeError::Invalid("unsigned integer too large")),
+            0xd0 => Ok(Value::Integer(i64::from(self.read_u8()? as i8))),
+            0xd1 => Ok(Value::Integer(i64::from(i16::from_be_bytes(
+                self.take_array()?,
+            )))),
+            0xd2 => Ok(Value::Integer(i64::from(i32::from_be_bytes(
+                self.take_array()?,
+            )))),
+            0xd3 => Ok(Value::Integer(i64::from_be_bytes(self.take_array()?))),
+            0xd9..=0xdb => {
+                let length = self.read_length(1 << (marker - 0xd9))?;
+                self.read_string(length)
+            }
+            0xdc | 0xdd => {
+                let length = self.read_length(2 << (marker - 0xdc))?;
+                self.read_array(length)
+            }
+            0xe0..=0xff => Ok(Value::Integer(i64::from(marker as i8))),
+            // Maps
+            0x80..=0x8f => {
+                self.skip_map(usize::from(marker & 0x0f))?;
+                Ok(self.other(start))
+            }
+            0xde | 0xdf => {
+                let length = self.read_length(2 << (marker - 0xde))?;
+                self.skip_map(length)?;
+                Ok(self.other(start))
+            }
+            // Binary data
+            0xc4..=0xc6 => {
+                let length = self.read_length(1 << (marker - 0xc4))?;
+                self.take(length)?;
+                Ok(self.other(start))
+            }
+            // Extensions
+            0xc7..=0xc9 => {
+                let length = self.read_length(1 << (marker - 0xc7))?;
+                self.skip_extension(length)?;
+                Ok(self.other(start))
+            }
+            0xd4..=0xd8 => {
+                self.skip_extension(1 << (marker - 0xd4))?;
+                Ok(self.other(start))
+            }
+            // Floats
+            0xca | 0xcb => {
+                self.take(4 << (marker - 0xca))?;
+                Ok(self.other(start))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+
+    use super::Value;
+
+    fn encode(value: &Value) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        value.encode(&mut buffer);
+        buffer
+    }
+
+    /// Encode `value`, check its marker, and decode it again.
+    fn assert_round_trip(value: &Value, marker: u8) {
+        let buffer = encode(value);
+        assert_eq!(buffer[0], marker, "marker of {value:?}");
+        let (decoded, length) = Value::decode(&buffer).unwrap().unwrap();
+        assert_eq!(&decoded, value);
+        assert_eq!(length, buffer.len());
+    }
+
+    #[test]
+    fn round_trip_scalars() {
+        assert_round_trip(&Value::Nil, 0xc0);
+        assert_round_trip(&Value::Boolean(false), 0xc2);
+        assert_round_trip(&Value::Boolean(true), 0xc3);
+        assert_round_trip(&Value::Integer(0), 0x00);
+        assert_round_trip(&Value::Integer(0x7f), 0x7f);
+        assert_round_trip(&Value::Integer(-1), 0xff);
+        assert_round_trip(&Value::Integer(-32), 0xe0);
+        assert_round_trip(&Value::Integer(0x80), 0xcf);
+        assert_round_trip(&Value::Integer(i64::MAX), 0xcf);
+        assert_round_trip(&Value::Integer(-33), 0xd3);
+        assert_round_trip(&Value::Integer(i64::MIN), 0xd3);
+    }
+
+    #[test]
+    fn round_trip_strings() {
+        for (length, marker) in [
+            (0, 0xa0),
+            (31, 0xbf),
+            (32, 0xd9),
+            (255, 0xd9),
+            (256, 0xda),
+            (65535, 0xda),
+            (65536, 0xdb),
+        ] {
+            let value = "é".repeat(length / 2) + &"x".repeat(length % 2);
+            assert_round_trip(&Value::String(value), marker);
+        }
+    }
+
+    #[test]
+    fn round_trip_arrays() {
+        for (length, marker) in [
+            (0, 0x90),
+            (15, 0x9f),
+            (16, 0xdc),
+            (255, 0xdc),
+            (256, 0xdc),
+            (65535, 0xdc),
+            (65536, 0xdd),
+        ] {
+            assert_round_trip(&Value::Array(vec![Value::Nil; length]), marker);
+        }
+    }
+
+    #[test]
+    fn round_trip_request() {
+        assert_round_trip(
+            &Value::Array(vec![
+                Value::from(0),
+                Value::from(1),
+                Value::from("nvim_command"),
+                Value::Array(vec![Value::from("set background=dark")]),
+            ]),
+            0x94,
+        );
+    }
+
+    #[test]
+    fn decode_integer_markers() {
+        let cases: &[(&[u8], i64)] = &[
+            (&[0xcc, 0xff], 0xff),
+            (&[0xcd, 0xff, 0xff], 0xffff),
+            (&[0xce, 0xff, 0xff, 0xff, 0xff], 0xffff_ffff),
+            (&[0xd0, 0x80], -0x80),
+            (&[0xd1, 0x80, 0x00], -0x8000),
+            (&[0xd2, 0x80, 0x00, 0x00, 0x00], -0x8000_0000),
+        ];
+        for (buffer, value) in cases {
+            assert_eq!(
+                Value::decode(buffer).unwrap(),
+                Some((Value::Integer(*value), buffer.len())),
+                "decode {buffer:x?}"
+            );
+        }
+    }
+
+    /// Encoded values of types we only skip over.
+    fn other_values() -> Vec<Vec<u8>> {
+        let mut bin16 = vec![0xc5, 0x01, 0x00];
+        bin16.extend_from_slice(&[0xaa; 256]);
+        let mut map16 = vec![0xde, 0x00, 0x10];
+        for key in 0..16 {
+            map16.extend_from_slice(&[key, 0xc0]);
+        }
+        vec![
+            vec![0xca, 0x3f, 0xc0, 0x00, 0x00],
+            vec![0xcb, 0x3f, 0xf8, 0, 0, 0, 0, 0, 0],
+            vec![0xc4, 0x02, 0xaa, 0xbb],
+            bin16,
+            vec![0xc7, 0x02, 0x01, 0xaa, 0xbb],
+            vec![0xd4, 0x01, 0xaa],
+            vec![
+                0xd8, 0xff, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
+            ],
+            vec![0x81, 0xa1, b'k', 0x92, 0xc3, 0xca, 0, 0, 0, 0],
+            map16,
+        ]
+    }
+
+    #[test]
+    fn skip_other_values() {
+        for bytes in other_values() {
+            let mut buffer = bytes.clone();
+            buffer.push(0xc0);
+            assert_eq!(
+                Value::decode(&buffer).unwrap(),
+                Some((Value::Other(bytes.clone()), bytes.len())),
+                "decode {bytes:x?}"
+            );
+            // Skipped values round-trip as well
+            assert_eq!(encode(&Value::Other(bytes.clone())), bytes);
+        }
+    }
+
+    #[test]
+    fn skip_other_values_in_arrays() {
+        let buffer = [
+            0x93, 0x01, 0x81, 0x01, 0xcb, 0, 0, 0, 0, 0, 0, 0, 0, 0xa1, b'x',
+        ];
+        assert_eq!(
+            Value::decode(&buffer).unwrap(),
+            Some((
+                Value::Array(vec![
+                    Value::Integer(1),
+                    Value::Other(buffer[2..13].to_vec()),
+                    Value::from("x")
+                ]),
+                buffer.len()
+            ))
+        );
+    }
+
+    #[test]
+    fn decode_partial_buffers() {
+        let mut buffers = vec![
+            encode(&Value::Integer(-33)),
+            encode(&Value::String("x".repeat(300))),
+            encode(&Value::Array((0..20).map(Value::Integer).collect())),
+        ];
+        buffers.extend(other_values());
+        for buffer in buffers {
+            for end in 0..buffer.len() {
+                assert!(
+                    Value::decode(&buffer[..end]).unwrap().is_none(),
+                    "decode {end} bytes of {buffer:x?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn decode_reserved_marker() {
+        let error = Value::decode(&[0xc1]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        let error = Value::decode(&[0x91, 0xc1]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        let error = Value::decode(&[0x81, 0xc0, 0xc1]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn decode_invalid_data() {
+        for buffer in [
+            &[0xa1, 0xff][..],
+            &[0xcf, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+        ] {
+            let error = Value::decode(buffer).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData, "decode {buffer:x?}");
+        }
+    }
+}
@@ -0,0 +1,335 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    io::ErrorKind,
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+};
+
+use futures::{FutureExt, future::BoxFuture};
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::UnixStream,
+};
+use tracing::{Level, event};
+
+use crate::xdg;
+
+use super::{
+    Backend, ColorScheme, Diagnosis, apply, diagnose_program, msgpack::Value, spawn_blocking_io,
+};
+
+/// Configuration for the Neovim backend.
+///
+/// All commands are Ex commands, without the leading `:`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Whether to enable the Neovim backend.
+    pub enabled: bool,
+    /// The command to run if the user has no preference.
+    pub default_command: String,
+    /// The command to run if the user prefers light mode.
+    pub light_command: String,
+    /// The command to run if the user prefers dark mode.
+    pub dark_command: String,
+    /// How to apply colour schemes.
+    pub apply: apply::Config,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            default_command: "set background=light".to_owned(),
+            light_command: "set background=light".to_owned(),
+            dark_command: "set background=dark".to_owned(),
+            apply: apply::Config::default(),
+        }
+    }
+}
+
+impl Config {
+    /// The command to run for `color_scheme`.
+    fn command_for(&self, color_scheme: ColorScheme) -> &str {
+        match color_scheme {
+            ColorScheme::NoPreference => &self.default_command,
+            ColorScheme::PreferDark => &self.dark_command,
+            ColorScheme::PreferLight => &self.light_command,
+        }
+    }
+}
+
+/// Whether `name` is the name of a default Neovim server socket.
+///
+/// Neovim names its server sockets `nvim.<pid>.<counter>`.
+fn is_server_socket_name(name: &str) -> bool {
+    name.strip_prefix("nvim.")
+        .and_then(|name| name.split_once('.'))
+        .is_some_and(|(pid, counter)| {
+            [pid, counter]
+                .iter()
+                .all(|s| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()))
+        })
+}
+
+/// Find the server sockets of all running Neovim instances.
+///
+/// Look for sockets in `$XDG_RUNTIME_DIR` named like the default server
+/// sockets of Neovim.
+fn find_servers() -> std::io::Result<Vec<PathBuf>> {
+    let runtime_dir = xdg::runtime_dir()
+        .ok_or_else(|| std::io::Error::new(ErrorKind::NotFound, "XDG_RUNTIME_DIR not set"))?;
+    let mut servers = std::fs::read_dir(runtime_dir)?
+        .filter_map(Result::ok)
+        .filter(|entry| is_server_socket_name(&entry.file_name().to_string_lossy()))
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_socket()))
+        .map(|entry| entry.path())
+        .collect::<Vec<_>>();
+    servers.sort_unstable();
+    Ok(servers)
+}
+
+/// The message ID of our requests.
+///
+/// We send only a single request per connection, so a fixed ID suffices.
+const REQUEST_ID: i64 = 1;
+
+/// Get the result of the response to our request from `message`.
+///
+/// Return `None` if `message` is not the response to our request, e.g. a
+/// notification.
+fn response_result(message: &Value) -> Option<std::io::Result<()>> {
+    match message.as_array()? {
+        [kind, id, error, _result]
+            if kind.as_integer() == Some(1) && id.as_integer() == Some(REQUEST_ID) =>
+        {
+            if *error == Value::Nil {
+                Some(Ok(()))
+            } else {
+                // Neovim sends errors as an array of error type and message
+                let message = error
+                    .as_array()
+                    .and_then(|error| error.get(1))
+                    .and_then(Value::as_str)
+                    .map_or_else(|| format!("{error:?}"), ToOwned::to_owned);
+                Some(Err(std::io::Error::other(message)))
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Run `command` in the Neovim instance listening at `server`.
+///
+/// Call `nvim_command` over msgpack-RPC, and wait for the response.
+async fn send_command(server: &Path, command: &str) -> std::io::Result<()> {
+    let mut stream = UnixStream::connect(server).await?;
+    let request = Value::Array(vec![
+        Value::from(0),
+        Value::from(REQUEST_ID),
+        Value::from("nvim_command"),
+        Value::Array(vec![Value::from(command)]),
+    ]);
+    let mut buffer = Vec::new();
+    request.encode(&mut buffer);
+    stream.write_all(&buffer).await?;
+
+    let mut received = Vec::new();
+    loop {
+        while let Some((message, length)) = Value::decode(&received)? {
+            received.drain(..length);
+            if let Some(result) = response_result(&message) {
+                return result;
+            }
+        }
+        if stream.read_buf(&mut received).await? == 0 {
+            return Err(std::io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "Neovim closed the connection before responding",
+            ));
+        }
+    }
+}
+
+/// Apply the given [`ColorScheme`] to [Neovim](https://neovim.io/).
+///
+/// Find the server sockets of all running Neovim instances in
+/// `$XDG_RUNTIME_DIR`, and run the command for `color_scheme` from `config`
+/// in every instance over msgpack-RPC.  By default, this sets `background`
+/// which makes most colour schemes switch between their light and dark
+/// variants.
+///
+/// Log but otherwise ignore failures to update a single Neovim instance, since
+/// Neovim does not clean up sockets if it crashes.
+///
+/// If `dry_run` is true only log the commands, without changing anything.
+pub async fn apply_color_scheme(
+    config: &Config,
+    color_scheme: ColorScheme,
+    dry_run: bool,
+) -> std::io::Result<()> {
+    let servers = spawn_blocking_io(find_servers).await.inspect_err(|error| {
+        event!(Level::WARN, "Failed to find neovim instances: {error}");
+    })?;
+    event!(Level::DEBUG, "Found neovim servers: {servers:?}");
+    let command = config.command_for(color_scheme);
+    futures::future::join_all(servers.iter().map(|server| async move {
+        if dry_run {
+            event!(
+                Level::INFO,
+                "Would run :{command} in neovim at {}",
+                server.display()
+            );
+        } else {
+            event!(
+                Level::INFO,
+                "Running :{command} in neovim at {}",
+                server.display()
+            );
+            if let Err(error) = send_command(server, command).await {
+                event!(
+                    Level::WARN,
+                    "Failed to update neovim at {}: {error}",
+                    server.display()
+                );
+            }
+        }
+    }))
+    .await;
+    Ok(())
+}
+
+/// Check whether Neovim is set up for darklightd.
+///
+/// Check that Neovim is in `$PATH`, and that we can find running instances.
+pub fn diagnose() -> Vec<Diagnosis> {
+    vec![
+        diagnose_program("nvim", "neovim"),
+        match find_servers() {
+            Ok(servers) => {
+                Diagnosis::Passed(format!("Found {} running neovim instances", servers.len()))
+            }
+            Err(error) => Diagnosis::failed(
+                format!("Failed to find running neovim instances: {error}"),
+                "Make sure that XDG_RUNTIME_DIR is set for darklightd and for neovim",
+            ),
+        },
+    ]
+}
+
+/// A backend which applies colour schemes to Neovim.
+///
+/// See [`apply_color_scheme`].
+pub struct Neovim {
+    config: Config,
+    dry_run: bool,
+}
+
+impl Neovim {
+    /// Create a new Neovim backend from `config`.
+    ///
+    /// If `dry_run` is true only log what the backend would do.
+    pub fn new(config: &Config, dry_run: bool) -> Self {
+        Self {
+            config: config.clone(),
+            dry_run,
+        }
+    }
+}
+
+impl Backend for Neovim {
+    fn name(&self) -> &'static str {
+        "neovim"
+    }
+
+    fn apply(&self, color_scheme: ColorScheme) -> BoxFuture<'_, std::io::Result<()>> {
+        apply_color_scheme(&self.config, color_scheme, self.dry_run).boxed()
+    }
+
+    fn diagnose(&self) -> BoxFuture<'_, Vec<Diagnosis>> {
+        futures::future::ready(diagnose()).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{REQUEST_ID, Value, is_server_socket_name, response_result};
+
+    #[test]
+    fn server_socket_names() {
+        assert!(is_server_socket_name("nvim.1234.0"));
+        assert!(is_server_socket_name("nvim.1.42"));
+        for name in [
+            "nvim",
+            "nvim.",
+            "nvim.1234",
+            "nvim.1234.",
+            "nvim..0",
+            "nvim.1234.0.1",
+            "nvim.abc.0",
+            "nvim.1234.x",
+            "vim.1234.0",
+            "xnvim.1234.0",
+        ] {
+            assert!(!is_server_socket_name(name), "{name}");
+        }
+    }
+
+    fn response(id: i64, error: Value) -> Value {
+        Value::Array(vec![Value::from(1), Value::from(id), error, Value::Nil])
+    }
+
+    #[test]
+    fn response_result_ok() {
+        let result = response_result(&response(REQUEST_ID, Value::Nil));
+        assert!(matches!(result, Some(Ok(()))));
+    }
+
+    #[test]
+    fn response_result_error_with_message() {
+        let error = Value::Array(vec![
+            Value::from(0),
+            Value::from("Vim:E492: Not an editor command: foo"),
+        ]);
+        let error = response_result(&response(REQUEST_ID, error))
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Vim:E492: Not an editor command: foo");
+    }
+
+    #[test]
+    fn response_result_error_of_other_shape() {
+        let error = response_result(&response(REQUEST_ID, Value::from("oops")))
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(error.to_string(), format!("{:?}", Value::from("oops")));
+    }
+
+    #[test]
+    fn response_result_ignores_other_messages() {
+        // A notification
+        let notification = Value::Array(vec![
+            Value::from(2),
+            Value::from("nvim_buf_lines_event"),
+            Value::Array(Vec::new()),
+        ]);
+        assert!(response_result(&notification).is_none());
+        // A response to another request
+        assert!(response_result(&response(REQUEST_ID + 1, Value::Nil)).is_none());
+        // A request to us
+        let request = Value::Array(vec![
+            Value::from(0),
+            Value::from(REQUEST_ID),
+            Value::from("nvim_get_api_info"),
+            Value::Array(Vec::new()),
+        ]);
+        assert!(response_result(&request).is_none());
+        assert!(response_result(&Value::from(1)).is_none());
+    }
+}
@@ -0,0 +1,145 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Find running processes and signal them.
+
+use std::{
+    ffi::OsString,
+    fs::{DirEntry, File},
+    io::{ErrorKind, Read},
+    os::{
+        fd::{AsFd, OwnedFd},
+        unix::ffi::OsStringExt,
+    },
+    path::PathBuf,
+};
+
+use rustix::{
+    fs::{Mode, OFlags, openat, readlinkat},
+    process::{Signal, pidfd_send_signal},
+};
+use tokio::task::JoinSet;
+use tracing::{Level, event};
+
+/// Get the file name of the executable of `process`.
+///
+/// `process` is a file descriptor for a directory in `/proc`.
+pub fn executable_name<F: AsFd>(process: F) -> std::io::Result<Option<String>> {
+    let target = PathBuf::from(OsString::from_vec(
+        readlinkat(process.as_fd(), "exe", Vec::new())?.into_bytes(),
+    ));
+    Ok(target
+        .file_name()
+        .and_then(|s| s.to_str())
+        .map(ToOwned::to_owned))
+}
+
+/// Get the first field of the command line of `process`.
+///
+/// `process` is a file descriptor for a directory in `/proc`.
+pub fn argv0<F: AsFd>(process: F) -> std::io::Result<String> {
+    let mut source: File =
+        openat(process.as_fd(), "cmdline", OFlags::CLOEXEC, Mode::empty())?.into();
+    let mut cmdline = String::new();
+    source.read_to_string(&mut cmdline)?;
+    Ok(cmdline.split('\0').next().unwrap_or_default().to_owned())
+}
+
+/// A human readable name for `signal`, for log messages.
+fn signal_name(signal: Signal) -> String {
+    match signal {
+        Signal::USR1 => "USR1".to_owned(),
+        Signal::USR2 => "USR2".to_owned(),
+        Signal::HUP => "HUP".to_owned(),
+        other => format!("signal {}", other.as_raw()),
+    }
+}
+
+fn process_dentry<M>(
+    dentry: &DirEntry,
+    matches: M,
+    signal: Signal,
+    application: &str,
+    dry_run: bool,
+) -> std::io::Result<()>
+where
+    M: Fn(&OwnedFd) -> std::io::Result<bool>,
+{
+    let pidfd = rustix::fs::open(
+        dentry.path(),
+        OFlags::DIRECTORY | OFlags::CLOEXEC,
+        Mode::empty(),
+    )?;
+    if matches(&pidfd)? {
+        if dry_run {
+            event!(
+                Level::INFO,
+                "Would send {} to presumed {application} process {}",
+                signal_name(signal),
+                dentry.file_name().to_string_lossy()
+            );
+            return Ok(());
+        }
+        event!(
+            Level::INFO,
+            "Sending {} to presumed {application} process {}",
+            signal_name(signal),
+            dentry.file_name().to_string_lossy()
+        );
+        pidfd_send_signal(pidfd, signal)?;
+    }
+    Ok(())
+}
+
+/// Send `signal` to all processes for which `matches` returns true.
+///
+/// Call `matches` with a file descriptor for the directory of each process in
+/// `/proc`.  Use `application` in log messages.
+///
+/// If `dry_run` is true only log the processes to signal, without sending any
+/// signal.
+pub fn signal_matching_processes<M>(
+    matches: M,
+    signal: Signal,
+    application: &'static str,
+    dry_run: bool,
+) -> JoinSet<()>
+where
+    M: Fn(&OwnedFd) -> std::io::Result<bool> + Clone + Send + 'static,
+{
+    let mut process_tasks = JoinSet::new();
+    match std::fs::read_dir("/proc") {
+        Err(error) => event!(Level::ERROR, "Failed to open /proc for reading: {error}"),
+        Ok(dentries) => {
+            for dentry in dentries.flatten() {
+                let matches = matches.clone();
+                process_tasks.spawn_blocking(move || {
+                    if let Err(error) =
+                        process_dentry(&dentry, matches, signal, application, dry_run)
+                    {
+                        match error.kind() {
+                            // Don't log if we've been looking at processes we
+                            // don't have permission to access, process that
+                            // we shortlived and vanished while we were looking
+                            // at them, and other non-directory things in /proc.
+                            ErrorKind::PermissionDenied
+                            | ErrorKind::NotFound
+                            | ErrorKind::NotADirectory => {}
+                            _ => {
+                                event!(
+                                    Level::DEBUG,
+                                    "Failed to handle dentry {}: {error}",
+                                    dentry.path().display()
+                                );
+                            }
+                        }
+                    }
+                });
+            }
+        }
+    }
+    process_tasks
+}
@@ -0,0 +1,280 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Switch themes of applications by linking theme files.
+
+use std::{
+    fs::{OpenOptions, exists, rename},
+    io::{ErrorKind, Write},
+    os::unix::fs::{OpenOptionsExt, PermissionsExt, symlink},
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use tracing::{Level, event};
+
+use super::{ColorScheme, Diagnosis};
+
+/// Theme files for each colour scheme, by file name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThemeFiles {
+    /// The theme file to use if the user has no preference, and as fallback.
+    pub default: String,
+    /// The theme file to use if the user prefers light mode.
+    pub light: String,
+    /// The theme file to use if the user prefers dark mode.
+    pub dark: String,
+    /// The file name to link the current theme file to.
+    pub auto: String,
+}
+
+impl ThemeFiles {
+    /// The theme file to use for `color_scheme`.
+    pub fn for_color_scheme(&self, color_scheme: ColorScheme) -> &str {
+        match color_scheme {
+            ColorScheme::NoPreference => &self.default,
+            ColorScheme::PreferDark => &self.dark,
+            ColorScheme::PreferLight => &self.light,
+        }
+    }
+}
+
+/// Theme names for each colour scheme, as configured in a backend table.
+///
+/// Backends which link theme files flatten this into their configuration.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+// The fields name the keys in backend tables
+#[allow(clippy::struct_field_names)]
+pub struct ThemeNames {
+    /// The theme to use if the user has no preference, and as fallback.
+    pub default_theme: String,
+    /// The theme to use if the user prefers light mode.
+    pub light_theme: String,
+    /// The theme to use if the user prefers dark mode.
+    pub dark_theme: String,
+    /// The theme to link the current theme to.
+    pub auto_theme: String,
+}
+
+impl Default for ThemeNames {
+    fn default() -> Self {
+        Self {
+            default_theme: concat!(env!("CARGO_PKG_NAME"), "-default").to_owned(),
+            light_theme: concat!(env!("CARGO_PKG_NAME"), "-light").to_owned(),
+            dark_theme: concat!(env!("CARGO_PKG_NAME"), "-dark").to_owned(),
+            auto_theme: concat!(env!("CARGO_PKG_NAME"), "-auto").to_owned(),
+        }
+    }
+}
+
+impl ThemeNames {
+    /// The file names of all themes, by appending `extension` to each name.
+    pub fn files(&self, extension: &str) -> ThemeFiles {
+        ThemeFiles {
+            default: format!("{}{extension}", self.default_theme),
+            light: format!("{}{extension}", self.light_theme),
+            dark: format!("{}{extension}", self.dark_theme),
+            auto: format!("{}{extension}", self.auto_theme),
+        }
+    }
+}
+
+fn has_theme(theme_dir: &Path, filename: &str) -> bool {
+    exists(theme_dir.join(filename)).unwrap_or_default()
+}
+
+/// A hidden temporary path next to `path`, with a random suffix.
+fn temp_path_for(path: &Path) -> PathBuf {
+    let random_suffix = std::iter::from_fn(|| Some(fastrand::alphanumeric()))
+        .take(10)
+        .collect::<String>();
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{file_name}-{random_suffix}"))
+}
+
+/// Atomically link `target` at `link`.
+///
+/// Create a link at a temporary name and then rename it to `link`, to replace
+/// `link` atomically; otherwise there might be a brief window where `link` does
+/// not exist.
+pub fn atomic_symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    let temp_link = temp_path_for(link);
+    event!(
+        Level::DEBUG,
+        "Linking {} at {}",
+        target.display(),
+        temp_link.display()
+    );
+    symlink(target, &temp_link)?;
+    rename(&temp_link, link)
+}
+
+/// Atomically write `contents` to `path`.
+///
+/// Write to a temporary file first and then rename it to `path`, so that
+/// applications watching `path` never see a partially written file.  If `path`
+/// already exists give the temporary file the permissions of `path`, so that
+/// replacing `path` keeps its mode.
+pub fn atomic_write(path: &Path, contents: &str) -> std::io::Result<()> {
+    let permissions = match std::fs::metadata(path) {
+        Ok(metadata) => Some(metadata.permissions()),
+        Err(error) if error.kind() == ErrorKind::NotFound => None,
+        Err(error) => return Err(error),
+    };
+    let temp_path = temp_path_for(path);
+    event!(Level::DEBUG, "Writing {}", temp_path.display());
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    if let Some(permissions) = &permissions {
+        // Never make the contents more accessible than the original file
+        options.mode(permissions.mode());
+    }
+    let mut file = options.open(&temp_path)?;
+    file.write_all(contents.as_bytes())?;
+    if let Some(permissions) = permissions {
+        // The umask may have masked some bits of the mode
+        file.set_permissions(permissions)?;
+    }
+    drop(file);
+    rename(&temp_path, path)
+}
+
+/// Link the theme file for `color_scheme` at the auto theme file in `directory`.
+///
+/// Fall back to the default theme file if the theme file for `color_scheme`
+/// does not exist in `directory`.  Link atomically, see [`atomic_symlink`].
+/// Use `application` in log messages.
+///
+/// If `dry_run` is true only log the link, without changing anything.
+///
+/// Fail with [`ErrorKind::NotFound`] if neither the theme file for
+/// `color_scheme` nor the default theme file exist.
+pub fn link_theme(
+    directory: &Path,
+    files: &ThemeFiles,
+    color_scheme: ColorScheme,
+    application: &str,
+    dry_run: bool,
+) -> std::io::Result<()> {
+    let theme_filename = files.for_color_scheme(color_scheme);
+    let theme_to_use = if has_theme(directory, theme_filename) {
+        theme_filename
+    } else {
+        event!(
+            Level::DEBUG,
+            "Theme {theme_filename} does not exist, falling back to {}",
+            files.default
+        );
+        &files.default
+    };
+
+    if has_theme(directory, theme_to_use) {
+        let auto_theme_file = directory.join(&files.auto);
+        if dry_run {
+            event!(
+                Level::INFO,
+                "Would link {theme_to_use} at {} to apply {color_scheme:?} to {application}",
+                auto_theme_file.display()
+            );
+            return Ok(());
+        }
+        event!(
+            Level::INFO,
+            "Linking {theme_to_use} at {} to apply {color_scheme:?} to {application}",
+            auto_theme_file.display()
+        );
+        atomic_symlink(Path::new(theme_to_use), &auto_theme_file)
+    } else {
+        event!(
+            Level::WARN,
+            "None of {theme_filename} or {} exist in {}, not applying {color_scheme:?} to {application}",
+            files.default,
+            directory.display()
+        );
+        Err(std::io::Error::new(
+            ErrorKind::NotFound,
+            format!("No {application} themes found for darklightd"),
+        ))
+    }
+}
+
+/// Check whether all theme files exist in `directory`.
+///
+/// Suggest `hint` for creating missing theme files.
+pub fn diagnose_theme_files(directory: &Path, files: &ThemeFiles, hint: &str) -> Vec<Diagnosis> {
+    [
+        (&files.default, "to use without colour scheme preference"),
+        (&files.light, "to use in light mode"),
+        (&files.dark, "to use in dark mode"),
+    ]
+    .into_iter()
+    .map(|(filename, purpose)| {
+        if has_theme(directory, filename) {
+            Diagnosis::Passed(format!("Theme {filename} exists"))
+        } else {
+            Diagnosis::failed(
+                format!("Theme {filename} missing in {}", directory.display()),
+                format!(
+                    "Create {} {purpose}, {hint}",
+                    directory.join(filename).display()
+                ),
+            )
+        }
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::Permissions,
+        os::unix::fs::PermissionsExt,
+        path::{Path, PathBuf},
+    };
+
+    use super::atomic_write;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!(
+            "{}-test-{name}-{}",
+            env!("CARGO_PKG_NAME"),
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn mode(path: &Path) -> u32 {
+        std::fs::metadata(path).unwrap().permissions().mode() & 0o7777
+    }
+
+    #[test]
+    fn atomic_write_creates_file() {
+        let directory = temp_dir("atomic-write-create");
+        let path = directory.join("settings.json");
+        atomic_write(&path, "{}\n").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{}\n");
+        // Only the file itself, without a temporary file left behind
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 1);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn atomic_write_keeps_mode_of_existing_file() {
+        let directory = temp_dir("atomic-write-mode");
+        for original_mode in [0o600, 0o640, 0o755] {
+            let path = directory.join(format!("settings-{original_mode:o}.json"));
+            std::fs::write(&path, "old").unwrap();
+            std::fs::set_permissions(&path, Permissions::from_mode(original_mode)).unwrap();
+            atomic_write(&path, "new").unwrap();
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+            assert_eq!(mode(&path), original_mode, "{}", path.display());
+        }
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -4,24 +4,42 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
+use std::fmt::Display;
+
+use clap::ValueEnum;
+
 /// Color scheme preferences.
-#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+#[derive(Debug, Eq, PartialEq, Clone, Copy, ValueEnum)]
 pub enum ColorScheme {
     /// The user has no preference for light or dark mode.
     ///
     /// In Gnome this is a light mode for applications, but with a dark shell panel.
     /// Gnome also uses this value if the user did not select dark mode.
+    #[value(name = "default")]
     NoPreference,
     /// The user explicitly wants dark mode.
+    #[value(name = "dark")]
     PreferDark,
     /// The user explicitly wants light mode.
     ///
     /// In Gnome this is light mode for applications combined with a light shell panel,
     /// provided by a built-in extension.  As of GNOME 47 users cannot explicitly
     /// select this mode in the settings UI; it's still experimental.
+    #[value(name = "light")]
     PreferLight,
 }
 
+impl Display for ColorScheme {
+    /// Format as `default`, `dark`, or `light`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColorScheme::NoPreference => write!(f, "default"),
+            ColorScheme::PreferDark => write!(f, "dark"),
+            ColorScheme::PreferLight => write!(f, "light"),
+        }
+    }
+}
+
 impl From<u32> for ColorScheme {
     /// Convert from an integer color scheme value.
     ///
@@ -36,3 +54,43 @@ impl From<u32> for ColorScheme {
         }
     }
 }
+
+impl From<ColorScheme> for u32 {
+    /// Convert to an integer color scheme value, as used by the settings portal.
+    fn from(value: ColorScheme) -> Self {
+        match value {
+            ColorScheme::NoPreference => 0,
+            ColorScheme::PreferDark => 1,
+            ColorScheme::PreferLight => 2,
+        }
+    }
+}
+
+/// The result of a single diagnostic check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Diagnosis {
+    /// The check passed; describes what was checked.
+    Passed(String),
+    /// The check failed.
+    Failed {
+        /// What's wrong.
+        problem: String,
+        /// How to fix the problem.
+        fix: String,
+    },
+}
+
+impl Diagnosis {
+    /// Create a failed diagnosis from `problem` and `fix`.
+    pub fn failed(problem: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self::Failed {
+            problem: problem.into(),
+            fix: fix.into(),
+        }
+    }
+
+    /// Whether this diagnosis failed.
+    pub fn is_failed(&self) -> bool {
+        matches!(self, Self::Failed { .. })
+    }
+}
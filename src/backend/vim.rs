@@ -0,0 +1,320 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::path::PathBuf;
+
+use futures::{FutureExt, future::BoxFuture};
+use serde::Deserialize;
+use tokio::process::Command;
+use tracing::{Level, event};
+
+use crate::xdg;
+
+use super::{
+    Backend, ColorScheme, Diagnosis, apply, command_output, diagnose_program, run_command,
+    spawn_blocking_io, theme,
+};
+
+/// Configuration for the Vim backend.
+///
+/// All commands are Ex commands, without the leading `:`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Whether to enable the Vim backend.
+    pub enabled: bool,
+    /// The command to run if the user has no preference.
+    pub default_command: String,
+    /// The command to run if the user prefers light mode.
+    pub light_command: String,
+    /// The command to run if the user prefers dark mode.
+    pub dark_command: String,
+    /// How to apply colour schemes.
+    pub apply: apply::Config,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            default_command: "set background=light".to_owned(),
+            light_command: "set background=light".to_owned(),
+            dark_command: "set background=dark".to_owned(),
+            apply: apply::Config::default(),
+        }
+    }
+}
+
+impl Config {
+    /// The command to run for `color_scheme`.
+    fn command_for(&self, color_scheme: ColorScheme) -> &str {
+        match color_scheme {
+            ColorScheme::NoPreference => &self.default_command,
+            ColorScheme::PreferDark => &self.dark_command,
+            ColorScheme::PreferLight => &self.light_command,
+        }
+    }
+}
+
+/// The name of the generated Vim script.
+const SCRIPT_NAME: &str = concat!(env!("CARGO_PKG_NAME"), ".vim");
+
+/// Find the vimrc of the user.
+///
+/// Vim looks for `~/.vimrc`, `~/.vim/vimrc`, and `$XDG_CONFIG_HOME/vim/vimrc`,
+/// in this order.
+fn find_vimrc() -> Option<PathBuf> {
+    let home = xdg::user_home();
+    [
+        home.join(".vimrc"),
+        home.join(".vim").join("vimrc"),
+        xdg::config_home().join("vim").join("vimrc"),
+    ]
+    .into_iter()
+    .find(|path| path.is_file())
+}
+
+/// The directory of the user's Vim configuration.
+///
+/// Vim only adds `$XDG_CONFIG_HOME/vim` to its runtime path if it reads its
+/// vimrc from this directory; otherwise it uses `~/.vim`.
+fn vim_dir() -> PathBuf {
+    let xdg_dir = xdg::config_home().join("vim");
+    match find_vimrc() {
+        Some(vimrc) if vimrc.starts_with(&xdg_dir) => xdg_dir,
+        _ => xdg::user_home().join(".vim"),
+    }
+}
+
+/// Generate the Vim script for `color_scheme`.
+fn script_contents(config: &Config, color_scheme: ColorScheme) -> String {
+    format!(
+        "\" Generated by {}, do not edit.\n{}\n",
+        env!("CARGO_PKG_NAME"),
+        config.command_for(color_scheme)
+    )
+}
+
+/// Write the Vim script for `color_scheme` to the Vim configuration directory.
+///
+/// If `dry_run` is true only log the script, without changing anything.
+async fn write_script(
+    config: &Config,
+    color_scheme: ColorScheme,
+    dry_run: bool,
+) -> std::io::Result<()> {
+    let contents = script_contents(config, color_scheme);
+    let directory = vim_dir();
+    let path = directory.join(SCRIPT_NAME);
+    if dry_run {
+        event!(
+            Level::INFO,
+            "Would write {} to apply {color_scheme:?} to vim:\n{contents}",
+            path.display()
+        );
+        return Ok(());
+    }
+    event!(
+        Level::INFO,
+        "Writing {} to apply {color_scheme:?} to vim",
+        path.display()
+    );
+    spawn_blocking_io(move || {
+        std::fs::create_dir_all(directory)?;
+        theme::atomic_write(&path, &contents)
+    })
+    .await
+}
+
+/// List the names of all running Vim servers with `vim --serverlist`.
+async fn list_servers() -> std::io::Result<Vec<String>> {
+    let mut command = Command::new("vim");
+    command.arg("--serverlist");
+    let output = command_output(&mut command).await?;
+    Ok(output
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(ToOwned::to_owned)
+        .collect())
+}
+
+/// Keys which make Vim run the Ex `command`.
+///
+/// Go to normal mode first, and escape `<` to keep Vim from interpreting it
+/// as key notation.
+fn command_keys(command: &str) -> String {
+    format!("<C-\\><C-N>:{}<CR>", command.replace('<', "<lt>"))
+}
+
+/// Run `command` in the Vim server named `server` with `--remote-send`.
+async fn send_command(server: &str, command: &str, dry_run: bool) -> std::io::Result<()> {
+    let mut vim = Command::new("vim");
+    vim.args(["--servername", server, "--remote-send"])
+        .arg(command_keys(command));
+    if dry_run {
+        event!(Level::INFO, "Would run {vim:?}");
+        Ok(())
+    } else {
+        event!(Level::INFO, "Running {vim:?}");
+        run_command(&mut vim).await
+    }
+}
+
+/// Apply the given [`ColorScheme`] to [Vim](https://www.vim.org/).
+///
+/// Write the command for `color_scheme` from `config` to `darklightd.vim` in
+/// the Vim configuration directory, for new Vim instances to source from
+/// their vimrc.  Then list all running Vim servers with `vim --serverlist`,
+/// and send the command to every server with `vim --remote-send`.  By
+/// default, this sets `background` which makes most colour schemes switch
+/// between their light and dark variants.
+///
+/// Only Vim instances built with the `clientserver` feature and running
+/// under X11 are servers.  Log but otherwise ignore failures to list servers,
+/// e.g. if `vim` lacks `clientserver`, and failures to update a single server.
+///
+/// If `dry_run` is true only log the script and the commands, without
+/// changing anything.
+pub async fn apply_color_scheme(
+    config: &Config,
+    color_scheme: ColorScheme,
+    dry_run: bool,
+) -> std::io::Result<()> {
+    write_script(config, color_scheme, dry_run)
+        .await
+        .inspect_err(|error| {
+            event!(Level::WARN, "Failed to write vim script: {error}");
+        })?;
+    let servers = match list_servers().await {
+        Ok(servers) => servers,
+        Err(error) => {
+            // Most Vim builds lack clientserver, and without X11 there are no
+            // servers either; new instances still source the script.
+            event!(
+                Level::INFO,
+                "Failed to list vim servers, not updating running instances: {error}"
+            );
+            return Ok(());
+        }
+    };
+    event!(Level::DEBUG, "Found vim servers: {servers:?}");
+    let command = config.command_for(color_scheme);
+    futures::future::join_all(servers.iter().map(|server| async move {
+        if let Err(error) = send_command(server, command, dry_run).await {
+            event!(Level::WARN, "Failed to update vim server {server}: {error}");
+        }
+    }))
+    .await;
+    Ok(())
+}
+
+fn diagnose_vimrc() -> Diagnosis {
+    let Some(vimrc) = find_vimrc() else {
+        return Diagnosis::failed(
+            "No vimrc found",
+            format!("Create ~/.vimrc, and add 'runtime {SCRIPT_NAME}' to it"),
+        );
+    };
+    match std::fs::read_to_string(&vimrc) {
+        Ok(contents) if contents.lines().any(|line| line.contains(SCRIPT_NAME)) => {
+            Diagnosis::Passed(format!("{} sources {SCRIPT_NAME}", vimrc.display()))
+        }
+        Ok(_) => Diagnosis::failed(
+            format!("{} does not source {SCRIPT_NAME}", vimrc.display()),
+            format!("Add 'runtime {SCRIPT_NAME}' to {}", vimrc.display()),
+        ),
+        Err(error) => Diagnosis::failed(
+            format!("Failed to read {}: {error}", vimrc.display()),
+            format!("Add 'runtime {SCRIPT_NAME}' to {}", vimrc.display()),
+        ),
+    }
+}
+
+/// Check whether Vim is set up for darklightd.
+///
+/// Check that Vim is in `$PATH`, that the vimrc sources the generated script,
+/// and that we can list running servers.
+pub async fn diagnose() -> Vec<Diagnosis> {
+    vec![
+        diagnose_program("vim", "vim"),
+        diagnose_vimrc(),
+        match list_servers().await {
+            Ok(servers) => {
+                Diagnosis::Passed(format!("Found {} running vim servers", servers.len()))
+            }
+            Err(error) => Diagnosis::failed(
+                format!("Failed to list running vim servers: {error}"),
+                "Install a vim with the clientserver feature, e.g. gvim",
+            ),
+        },
+    ]
+}
+
+/// A backend which applies colour schemes to Vim.
+///
+/// See [`apply_color_scheme`].
+pub struct Vim {
+    config: Config,
+    dry_run: bool,
+}
+
+impl Vim {
+    /// Create a new Vim backend from `config`.
+    ///
+    /// If `dry_run` is true only log what the backend would do.
+    pub fn new(config: &Config, dry_run: bool) -> Self {
+        Self {
+            config: config.clone(),
+            dry_run,
+        }
+    }
+}
+
+impl Backend for Vim {
+    fn name(&self) -> &'static str {
+        "vim"
+    }
+
+    fn apply(&self, color_scheme: ColorScheme) -> BoxFuture<'_, std::io::Result<()>> {
+        apply_color_scheme(&self.config, color_scheme, self.dry_run).boxed()
+    }
+
+    fn diagnose(&self) -> BoxFuture<'_, Vec<Diagnosis>> {
+        diagnose().boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::command_keys;
+
+    #[test]
+    fn command_keys_go_to_normal_mode_and_run_command() {
+        assert_eq!(
+            command_keys("set background=dark"),
+            "<C-\\><C-N>:set background=dark<CR>"
+        );
+        assert_eq!(command_keys(""), "<C-\\><C-N>:<CR>");
+    }
+
+    #[test]
+    fn command_keys_escape_less_than() {
+        assert_eq!(
+            command_keys("colorscheme <sfile>"),
+            "<C-\\><C-N>:colorscheme <lt>sfile><CR>"
+        );
+        assert_eq!(
+            command_keys("if 1 < 2 | echo '<CR>' | endif"),
+            "<C-\\><C-N>:if 1 <lt> 2 | echo '<lt>CR>' | endif<CR>"
+        );
+        // Other characters stay as they are
+        assert_eq!(
+            command_keys("echo \"a>b\\\""),
+            "<C-\\><C-N>:echo \"a>b\\\"<CR>"
+        );
+    }
+}
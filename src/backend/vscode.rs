@@ -0,0 +1,181 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::path::PathBuf;
+
+use futures::{FutureExt, future::BoxFuture};
+use serde::Deserialize;
+use tracing::{Level, event};
+
+use crate::xdg;
+
+use super::{Backend, ColorScheme, Diagnosis, apply, jsonc, spawn_blocking_io};
+
+/// The setting for the colour theme of VS Code.
+static COLOR_THEME: &str = "workbench.colorTheme";
+
+/// The setting which makes VS Code follow the system colour scheme by itself.
+static AUTO_DETECT_COLOR_SCHEME: &str = "window.autoDetectColorScheme";
+
+/// Configuration for the VS Code backend.
+///
+/// All themes are given by their name in VS Code.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Whether to enable the VS Code backend.
+    pub enabled: bool,
+    /// The name of the configuration directory of VS Code.
+    ///
+    /// E.g. `Code`, `Code - OSS`, or `VSCodium`.
+    pub product: String,
+    /// The theme to use if the user has no preference.
+    pub default_theme: String,
+    /// The theme to use if the user prefers light mode.
+    pub light_theme: String,
+    /// The theme to use if the user prefers dark mode.
+    pub dark_theme: String,
+    /// How to apply colour schemes.
+    pub apply: apply::Config,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            product: "Code".to_owned(),
+            default_theme: "Default Light Modern".to_owned(),
+            light_theme: "Default Light Modern".to_owned(),
+            dark_theme: "Default Dark Modern".to_owned(),
+            apply: apply::Config::default(),
+        }
+    }
+}
+
+impl Config {
+    /// The theme to use for `color_scheme`.
+    fn theme_for(&self, color_scheme: ColorScheme) -> &str {
+        match color_scheme {
+            ColorScheme::NoPreference => &self.default_theme,
+            ColorScheme::PreferDark => &self.dark_theme,
+            ColorScheme::PreferLight => &self.light_theme,
+        }
+    }
+
+    /// The path to the user settings of VS Code.
+    fn settings_file(&self) -> PathBuf {
+        xdg::config_home()
+            .join(&self.product)
+            .join("User")
+            .join("settings.json")
+    }
+}
+
+/// Apply the given [`ColorScheme`] to [VS Code](https://code.visualstudio.com/).
+///
+/// Set `workbench.colorTheme` in the user settings of VS Code to the theme for
+/// `color_scheme` from `config`.  Edit the settings in place, to keep all
+/// comments and formatting, and write the settings atomically, see
+/// [`jsonc::edit_file`].  VS Code watches its settings file, and applies
+/// the new theme immediately.
+///
+/// If `dry_run` is true only log the new theme, without changing anything.
+pub async fn apply_color_scheme(
+    config: &Config,
+    color_scheme: ColorScheme,
+    dry_run: bool,
+) -> std::io::Result<()> {
+    let settings_file = config.settings_file();
+    let theme = config.theme_for(color_scheme).to_owned();
+    spawn_blocking_io(move || {
+        jsonc::edit_file(
+            &settings_file,
+            &format!("set {COLOR_THEME} to {theme}"),
+            dry_run,
+            |contents| jsonc::set_string(contents, &[COLOR_THEME], &theme),
+        )
+    })
+    .await
+    .inspect_err(|error| {
+        event!(Level::WARN, "Failed to update vscode settings: {error}");
+    })
+}
+
+/// Check whether VS Code is set up for darklightd.
+///
+/// Check that we can parse the VS Code settings, and that VS Code does not
+/// follow the system colour scheme by itself, which would override the theme
+/// we set.
+pub fn diagnose(config: &Config) -> Vec<Diagnosis> {
+    let settings_file = config.settings_file();
+    let contents = match std::fs::read_to_string(&settings_file) {
+        Ok(contents) => contents,
+        Err(error) => {
+            return vec![Diagnosis::failed(
+                format!("Failed to read {}: {error}", settings_file.display()),
+                format!(
+                    "Start VS Code once, or set product in the [vscode] table to the name of the directory containing User/settings.json in {}",
+                    xdg::config_home().display()
+                ),
+            )];
+        }
+    };
+    let diagnosis = match jsonc::get(&contents, &[AUTO_DETECT_COLOR_SCHEME]) {
+        Err(error) => Diagnosis::failed(
+            format!("Failed to parse {}: {error}", settings_file.display()),
+            format!("Fix the syntax of {}", settings_file.display()),
+        ),
+        Ok(Some("true")) => Diagnosis::failed(
+            format!(
+                "{} enables {AUTO_DETECT_COLOR_SCHEME}, which overrides {COLOR_THEME}",
+                settings_file.display()
+            ),
+            format!(
+                "Remove {AUTO_DETECT_COLOR_SCHEME} from {}",
+                settings_file.display()
+            ),
+        ),
+        Ok(_) => Diagnosis::Passed(format!(
+            "{} does not enable {AUTO_DETECT_COLOR_SCHEME}",
+            settings_file.display()
+        )),
+    };
+    vec![diagnosis]
+}
+
+/// A backend which applies colour schemes to VS Code.
+///
+/// See [`apply_color_scheme`].
+pub struct VsCode {
+    config: Config,
+    dry_run: bool,
+}
+
+impl VsCode {
+    /// Create a new VS Code backend from `config`.
+    ///
+    /// If `dry_run` is true only log what the backend would do.
+    pub fn new(config: &Config, dry_run: bool) -> Self {
+        Self {
+            config: config.clone(),
+            dry_run,
+        }
+    }
+}
+
+impl Backend for VsCode {
+    fn name(&self) -> &'static str {
+        "vscode"
+    }
+
+    fn apply(&self, color_scheme: ColorScheme) -> BoxFuture<'_, std::io::Result<()>> {
+        apply_color_scheme(&self.config, color_scheme, self.dry_run).boxed()
+    }
+
+    fn diagnose(&self) -> BoxFuture<'_, Vec<Diagnosis>> {
+        futures::future::ready(diagnose(&self.config)).boxed()
+    }
+}
@@ -0,0 +1,230 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{fmt::Write, path::PathBuf};
+
+use futures::{FutureExt, future::BoxFuture};
+use serde::Deserialize;
+use tracing::{Level, event};
+
+use crate::xdg;
+
+use super::{Backend, ColorScheme, Diagnosis, apply, diagnose_program, spawn_blocking_io, theme};
+
+/// Configuration for the WezTerm backend.
+///
+/// All colour schemes are given by their name in WezTerm.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Whether to enable the WezTerm backend.
+    pub enabled: bool,
+    /// The colour scheme to use if the user has no preference.
+    pub default_scheme: String,
+    /// The colour scheme to use if the user prefers light mode.
+    pub light_scheme: String,
+    /// The colour scheme to use if the user prefers dark mode.
+    pub dark_scheme: String,
+    /// How to apply colour schemes.
+    pub apply: apply::Config,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            default_scheme: "Builtin Light".to_owned(),
+            light_scheme: "Builtin Light".to_owned(),
+            dark_scheme: "Builtin Dark".to_owned(),
+            apply: apply::Config::default(),
+        }
+    }
+}
+
+impl Config {
+    /// The WezTerm colour scheme to use for `color_scheme`.
+    fn scheme_for(&self, color_scheme: ColorScheme) -> &str {
+        match color_scheme {
+            ColorScheme::NoPreference => &self.default_scheme,
+            ColorScheme::PreferDark => &self.dark_scheme,
+            ColorScheme::PreferLight => &self.light_scheme,
+        }
+    }
+}
+
+/// The name of the generated Lua module.
+const MODULE_NAME: &str = env!("CARGO_PKG_NAME");
+
+fn wezterm_config_dir() -> PathBuf {
+    xdg::config_home().join("wezterm")
+}
+
+fn module_file() -> PathBuf {
+    wezterm_config_dir().join(format!("{MODULE_NAME}.lua"))
+}
+
+/// Quote `s` as a Lua string literal.
+fn lua_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if c.is_ascii_control() => write!(quoted, "\\{:03}", u32::from(c)).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Generate the Lua module for `color_scheme`.
+fn module_contents(config: &Config, color_scheme: ColorScheme) -> String {
+    format!(
+        "-- Generated by {}, do not edit.\nreturn {{\n  scheme = {},\n  color_scheme = {},\n}}\n",
+        env!("CARGO_PKG_NAME"),
+        lua_string(&color_scheme.to_string()),
+        lua_string(config.scheme_for(color_scheme)),
+    )
+}
+
+/// Apply the given [`ColorScheme`] to [WezTerm](https://wezterm.org/).
+///
+/// This function writes a Lua module `darklightd.lua` to
+/// `$XDG_CONFIG_HOME/wezterm`, which returns a table with the current colour
+/// scheme as `scheme` (`default`, `light`, or `dark`) and the corresponding
+/// WezTerm colour scheme from `config` as `color_scheme`.  WezTerm watches
+/// modules required by its configuration, and reloads its configuration
+/// whenever the module changes.
+///
+/// Write the module atomically, see [`theme::atomic_write`].
+///
+/// If `dry_run` is true only log the module, without changing anything.
+pub async fn apply_color_scheme(
+    config: &Config,
+    color_scheme: ColorScheme,
+    dry_run: bool,
+) -> std::io::Result<()> {
+    let contents = module_contents(config, color_scheme);
+    let path = module_file();
+    if dry_run {
+        event!(
+            Level::INFO,
+            "Would write {} to apply {color_scheme:?} to wezterm:\n{contents}",
+            path.display()
+        );
+        return Ok(());
+    }
+    event!(
+        Level::INFO,
+        "Writing {} to apply {color_scheme:?} to wezterm",
+        path.display()
+    );
+    spawn_blocking_io(move || {
+        std::fs::create_dir_all(wezterm_config_dir())?;
+        theme::atomic_write(&path, &contents)
+    })
+    .await
+    .inspect_err(|error| {
+        event!(Level::WARN, "Failed to update wezterm module: {error}");
+    })
+}
+
+fn diagnose_wezterm_config() -> Diagnosis {
+    let wezterm_config = wezterm_config_dir().join("wezterm.lua");
+    let fix = format!(
+        "Add 'local {MODULE_NAME} = require(\"{MODULE_NAME}\")' to {}, and set 'config.color_scheme = {MODULE_NAME}.color_scheme'",
+        wezterm_config.display()
+    );
+    match std::fs::read_to_string(&wezterm_config) {
+        Ok(contents)
+            if contents
+                .lines()
+                .any(|line| line.contains("require") && line.contains(MODULE_NAME)) =>
+        {
+            Diagnosis::Passed(format!(
+                "{} requires {MODULE_NAME}",
+                wezterm_config.display()
+            ))
+        }
+        Ok(_) => Diagnosis::failed(
+            format!(
+                "{} does not require {MODULE_NAME}",
+                wezterm_config.display()
+            ),
+            fix,
+        ),
+        Err(error) => Diagnosis::failed(
+            format!("Failed to read {}: {error}", wezterm_config.display()),
+            fix,
+        ),
+    }
+}
+
+/// Check whether WezTerm is set up for darklightd.
+///
+/// Check that WezTerm is in `$PATH`, and that the WezTerm configuration
+/// requires the generated module.
+pub fn diagnose() -> Vec<Diagnosis> {
+    vec![
+        diagnose_program("wezterm", "wezterm"),
+        diagnose_wezterm_config(),
+    ]
+}
+
+/// A backend which applies colour schemes to WezTerm.
+///
+/// See [`apply_color_scheme`].
+pub struct WezTerm {
+    config: Config,
+    dry_run: bool,
+}
+
+impl WezTerm {
+    /// Create a new WezTerm backend from `config`.
+    ///
+    /// If `dry_run` is true only log what the backend would do.
+    pub fn new(config: &Config, dry_run: bool) -> Self {
+        Self {
+            config: config.clone(),
+            dry_run,
+        }
+    }
+}
+
+impl Backend for WezTerm {
+    fn name(&self) -> &'static str {
+        "wezterm"
+    }
+
+    fn apply(&self, color_scheme: ColorScheme) -> BoxFuture<'_, std::io::Result<()>> {
+        apply_color_scheme(&self.config, color_scheme, self.dry_run).boxed()
+    }
+
+    fn diagnose(&self) -> BoxFuture<'_, Vec<Diagnosis>> {
+        futures::future::ready(diagnose()).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::lua_string;
+
+    #[test]
+    fn lua_string_escapes_quotes_and_backslashes() {
+        assert_eq!(lua_string(r#"a "b" \c"#), r#""a \"b\" \\c""#);
+        assert_eq!(lua_string("a\nb"), r#""a\nb""#);
+    }
+
+    #[test]
+    fn lua_string_uses_fixed_width_decimal_escapes() {
+        assert_eq!(lua_string("\x012"), r#""\0012""#);
+        assert_eq!(lua_string("\t"), r#""\009""#);
+        assert_eq!(lua_string("\x7f"), r#""\127""#);
+    }
+}
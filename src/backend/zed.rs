@@ -0,0 +1,267 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::path::PathBuf;
+
+use futures::{FutureExt, future::BoxFuture};
+use serde::Deserialize;
+use tracing::{Level, event};
+
+use crate::xdg;
+
+use super::{Backend, ColorScheme, Diagnosis, apply, jsonc, spawn_blocking_io};
+
+/// Configuration for the Zed backend.
+///
+/// All themes are given by their name in Zed.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Whether to enable the Zed backend.
+    pub enabled: bool,
+    /// The theme to use if the user has no preference.
+    pub default_theme: String,
+    /// The theme to use if the user prefers light mode.
+    pub light_theme: String,
+    /// The theme to use if the user prefers dark mode.
+    pub dark_theme: String,
+    /// How to apply colour schemes.
+    pub apply: apply::Config,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            default_theme: "One Light".to_owned(),
+            light_theme: "One Light".to_owned(),
+            dark_theme: "One Dark".to_owned(),
+            apply: apply::Config::default(),
+        }
+    }
+}
+
+impl Config {
+    /// The theme to use for `color_scheme`.
+    fn theme_for(&self, color_scheme: ColorScheme) -> &str {
+        match color_scheme {
+            ColorScheme::NoPreference => &self.default_theme,
+            ColorScheme::PreferDark => &self.dark_theme,
+            ColorScheme::PreferLight => &self.light_theme,
+        }
+    }
+}
+
+fn settings_file() -> PathBuf {
+    xdg::config_home().join("zed").join("settings.json")
+}
+
+/// The Zed theme mode for `color_scheme`.
+fn theme_mode(color_scheme: ColorScheme) -> &'static str {
+    match color_scheme {
+        ColorScheme::PreferDark => "dark",
+        ColorScheme::NoPreference | ColorScheme::PreferLight => "light",
+    }
+}
+
+/// Set the theme in the Zed settings in `contents` for `color_scheme`.
+///
+/// If the settings configure light and dark themes, i.e. `theme` is an object,
+/// set `theme.mode`; otherwise set `theme` to the theme for `color_scheme` from
+/// `config`.
+fn set_theme(
+    config: &Config,
+    contents: &str,
+    color_scheme: ColorScheme,
+) -> Result<String, jsonc::SyntaxError> {
+    let has_theme_modes =
+        jsonc::get(contents, &["theme"])?.is_some_and(|theme| theme.starts_with('{'));
+    if has_theme_modes {
+        jsonc::set_string(contents, &["theme", "mode"], theme_mode(color_scheme))
+    } else {
+        jsonc::set_string(contents, &["theme"], config.theme_for(color_scheme))
+    }
+}
+
+/// Apply the given [`ColorScheme`] to [Zed](https://zed.dev/).
+///
+/// If the Zed settings at `$XDG_CONFIG_HOME/zed/settings.json` have light and
+/// dark themes, set `theme.mode` to `dark` or `light`.  Otherwise set `theme`
+/// to the theme for `color_scheme` from `config`.  Edit the settings in place,
+/// to keep all comments and formatting, and write the settings atomically, see
+/// [`jsonc::edit_file`].  Zed watches its settings file, and applies the new
+/// theme immediately.
+///
+/// If `dry_run` is true only log the change, without changing anything.
+pub async fn apply_color_scheme(
+    config: &Config,
+    color_scheme: ColorScheme,
+    dry_run: bool,
+) -> std::io::Result<()> {
+    let config = config.clone();
+    spawn_blocking_io(move || {
+        jsonc::edit_file(
+            &settings_file(),
+            &format!("apply {color_scheme:?} to zed"),
+            dry_run,
+            |contents| set_theme(&config, contents, color_scheme),
+        )
+    })
+    .await
+    .inspect_err(|error| {
+        event!(Level::WARN, "Failed to update zed settings: {error}");
+    })
+}
+
+/// Check whether Zed is set up for darklightd.
+///
+/// Check that we can parse the Zed settings, and whether they have light and
+/// dark themes.
+pub fn diagnose() -> Vec<Diagnosis> {
+    let settings_file = settings_file();
+    let contents = match std::fs::read_to_string(&settings_file) {
+        Ok(contents) => contents,
+        Err(error) => {
+            return vec![Diagnosis::failed(
+                format!("Failed to read {}: {error}", settings_file.display()),
+                "Start Zed once to create its settings",
+            )];
+        }
+    };
+    let diagnosis = match jsonc::get(&contents, &["theme"]) {
+        Err(error) => Diagnosis::failed(
+            format!("Failed to parse {}: {error}", settings_file.display()),
+            format!("Fix the syntax of {}", settings_file.display()),
+        ),
+        Ok(Some(theme)) if theme.starts_with('{') => Diagnosis::Passed(format!(
+            "{} has light and dark themes, setting theme.mode",
+            settings_file.display()
+        )),
+        Ok(_) => Diagnosis::Passed(format!(
+            "{} has a single theme, setting theme from the [zed] table",
+            settings_file.display()
+        )),
+    };
+    vec![diagnosis]
+}
+
+/// A backend which applies colour schemes to Zed.
+///
+/// See [`apply_color_scheme`].
+pub struct Zed {
+    config: Config,
+    dry_run: bool,
+}
+
+impl Zed {
+    /// Create a new Zed backend from `config`.
+    ///
+    /// If `dry_run` is true only log what the backend would do.
+    pub fn new(config: &Config, dry_run: bool) -> Self {
+        Self {
+            config: config.clone(),
+            dry_run,
+        }
+    }
+}
+
+impl Backend for Zed {
+    fn name(&self) -> &'static str {
+        "zed"
+    }
+
+    fn apply(&self, color_scheme: ColorScheme) -> BoxFuture<'_, std::io::Result<()>> {
+        apply_color_scheme(&self.config, color_scheme, self.dry_run).boxed()
+    }
+
+    fn diagnose(&self) -> BoxFuture<'_, Vec<Diagnosis>> {
+        futures::future::ready(diagnose()).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ColorScheme, Config, set_theme};
+
+    const THEME_MODES: &str = r#"// Zed settings
+{
+  "theme": {
+    "mode": "system", // follow darklightd
+    "light": "One Light",
+    "dark": "One Dark"
+  },
+  "ui_font_size": 16
+}
+"#;
+
+    #[test]
+    fn set_theme_mode_if_theme_is_object() {
+        let config = Config::default();
+        for (color_scheme, mode) in [
+            (ColorScheme::PreferDark, "dark"),
+            (ColorScheme::PreferLight, "light"),
+            (ColorScheme::NoPreference, "light"),
+        ] {
+            assert_eq!(
+                set_theme(&config, THEME_MODES, color_scheme).unwrap(),
+                THEME_MODES.replace("\"system\"", &format!("\"{mode}\"")),
+            );
+        }
+    }
+
+    #[test]
+    fn add_theme_mode_if_theme_is_object_without_mode() {
+        let settings = "{\n  \"theme\": {\n    \"light\": \"One Light\",\n    \"dark\": \"One Dark\"\n  }\n}\n";
+        assert_eq!(
+            set_theme(&Config::default(), settings, ColorScheme::PreferDark).unwrap(),
+            "{\n  \"theme\": {\n    \"light\": \"One Light\",\n    \"dark\": \"One Dark\",\n    \"mode\": \"dark\"\n  }\n}\n"
+        );
+    }
+
+    #[test]
+    fn set_theme_if_theme_is_string() {
+        let config = Config {
+            default_theme: "Default".to_owned(),
+            light_theme: "Light".to_owned(),
+            dark_theme: "Dark".to_owned(),
+            ..Config::default()
+        };
+        let settings = "{\n  // My theme\n  \"theme\": \"Andromeda\",\n  \"ui_font_size\": 16\n}\n";
+        for (color_scheme, theme) in [
+            (ColorScheme::PreferDark, "Dark"),
+            (ColorScheme::PreferLight, "Light"),
+            (ColorScheme::NoPreference, "Default"),
+        ] {
+            assert_eq!(
+                set_theme(&config, settings, color_scheme).unwrap(),
+                settings.replace("Andromeda", theme),
+            );
+        }
+    }
+
+    #[test]
+    fn set_theme_if_theme_is_missing() {
+        let config = Config::default();
+        assert_eq!(
+            set_theme(&config, "", ColorScheme::PreferDark).unwrap(),
+            "{\n    \"theme\": \"One Dark\"\n}\n"
+        );
+        assert_eq!(
+            set_theme(
+                &config,
+                "{\n  \"vim_mode\": true\n}\n",
+                ColorScheme::PreferLight
+            )
+            .unwrap(),
+            "{\n  \"vim_mode\": true,\n  \"theme\": \"One Light\"\n}\n"
+        );
+    }
+
+    #[test]
+    fn set_theme_fails_on_invalid_settings() {
+        assert!(set_theme(&Config::default(), "{\"theme\": ", ColorScheme::PreferDark).is_err());
+    }
+}
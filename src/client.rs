@@ -0,0 +1,115 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::io::Write;
+
+use zbus::{proxy, proxy::CacheProperties};
+
+use crate::{backend::ColorScheme, manager::color_scheme_from_u32};
+
+/// The darklightd manager interface of the running daemon.
+///
+/// See [`crate::manager::Manager`].
+#[proxy(
+    default_service = "de.swsnr.darklightd",
+    default_path = "/de/swsnr/darklightd",
+    interface = "de.swsnr.darklightd.Manager",
+    gen_blocking = false
+)]
+trait Manager {
+    #[zbus(property)]
+    fn color_scheme(&self) -> zbus::Result<u32>;
+
+    #[zbus(property)]
+    fn desktop_color_scheme(&self) -> zbus::Result<u32>;
+
+    #[zbus(property)]
+    fn overridden(&self) -> zbus::Result<bool>;
+
+    #[zbus(property)]
+    fn backends(&self) -> zbus::Result<Vec<String>>;
+
+    #[zbus(name = "Override")]
+    fn set_override(&self, color_scheme: u32) -> zbus::Result<()>;
+
+    fn clear_override(&self) -> zbus::Result<()>;
+
+    fn reapply(&self) -> zbus::Result<()>;
+}
+
+async fn connect() -> zbus::Result<ManagerProxy<'static>> {
+    let connection = zbus::Connection::session().await?;
+    // We're short-lived, so don't bother to cache properties
+    ManagerProxy::builder(&connection)
+        .cache_properties(CacheProperties::No)
+        .build()
+        .await
+}
+
+async fn current_color_scheme(manager: &ManagerProxy<'_>) -> zbus::Result<ColorScheme> {
+    Ok(color_scheme_from_u32(manager.color_scheme().await?)?)
+}
+
+/// Print the current colour scheme of the running daemon.
+pub async fn get() -> Result<(), Box<dyn std::error::Error>> {
+    let color_scheme = current_color_scheme(&connect().await?).await?;
+    writeln!(std::io::stdout(), "{color_scheme}")?;
+    Ok(())
+}
+
+/// Override the colour scheme of the desktop with `color_scheme`.
+pub async fn set(color_scheme: ColorScheme) -> Result<(), Box<dyn std::error::Error>> {
+    connect().await?.set_override(color_scheme.into()).await?;
+    Ok(())
+}
+
+/// Clear any override of the colour scheme.
+pub async fn reset() -> Result<(), Box<dyn std::error::Error>> {
+    connect().await?.clear_override().await?;
+    Ok(())
+}
+
+/// Toggle between dark and light colour scheme.
+///
+/// Override the colour scheme with [`ColorScheme::PreferLight`] if the current
+/// colour scheme is [`ColorScheme::PreferDark`], and with
+/// [`ColorScheme::PreferDark`] otherwise.
+///
+/// Print the new colour scheme.
+pub async fn toggle() -> Result<(), Box<dyn std::error::Error>> {
+    let manager = connect().await?;
+    let new_color_scheme = match current_color_scheme(&manager).await? {
+        ColorScheme::PreferDark => ColorScheme::PreferLight,
+        ColorScheme::NoPreference | ColorScheme::PreferLight => ColorScheme::PreferDark,
+    };
+    manager.set_override(new_color_scheme.into()).await?;
+    writeln!(std::io::stdout(), "{new_color_scheme}")?;
+    Ok(())
+}
+
+/// Make all backends of the running daemon apply the current colour scheme again.
+pub async fn reapply() -> Result<(), Box<dyn std::error::Error>> {
+    connect().await?.reapply().await?;
+    Ok(())
+}
+
+/// Print the status of the running daemon.
+pub async fn status() -> Result<(), Box<dyn std::error::Error>> {
+    let manager = connect().await?;
+    let color_scheme = current_color_scheme(&manager).await?;
+    let desktop_color_scheme = color_scheme_from_u32(manager.desktop_color_scheme().await?)?;
+    let overridden = manager.overridden().await?;
+    let backends = manager.backends().await?;
+    let mut stdout = std::io::stdout().lock();
+    if overridden {
+        writeln!(stdout, "Color scheme: {color_scheme} (overridden)")?;
+    } else {
+        writeln!(stdout, "Color scheme: {color_scheme}")?;
+    }
+    writeln!(stdout, "Desktop color scheme: {desktop_color_scheme}")?;
+    writeln!(stdout, "Backends: {}", backends.join(", "))?;
+    Ok(())
+}
@@ -0,0 +1,355 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    ffi::OsStr,
+    fmt::Display,
+    io::ErrorKind,
+    mem::MaybeUninit,
+    os::{fd::OwnedFd, unix::ffi::OsStrExt},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use futures::{
+    StreamExt,
+    stream::{self, BoxStream},
+};
+use rustix::fs::inotify;
+use serde::Deserialize;
+use tokio::io::unix::AsyncFd;
+use tracing::{Level, event};
+
+use crate::{
+    backend::{
+        alacritty, emacs, foot, ghostty, gtk, helix, kitty, log, neovim, vim, vscode, wezterm, zed,
+    },
+    xdg,
+};
+
+/// The configuration of darklightd.
+///
+/// Each backend has its own table, named after the backend, which enables
+/// or disables the backend and carries further backend-specific options.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// How long the colour scheme must remain unchanged before backends apply
+    /// it, in milliseconds.
+    pub debounce_ms: u64,
+    /// Configuration for the log backend.
+    pub log: log::Config,
+    /// Configuration for the Gtk backend.
+    pub gtk: gtk::Config,
+    /// Configuration for the Helix backend.
+    pub helix: helix::Config,
+    /// Configuration for the kitty backend.
+    pub kitty: kitty::Config,
+    /// Configuration for the Alacritty backend.
+    pub alacritty: alacritty::Config,
+    /// Configuration for the foot backend.
+    pub foot: foot::Config,
+    /// Configuration for the WezTerm backend.
+    pub wezterm: wezterm::Config,
+    /// Configuration for the Ghostty backend.
+    pub ghostty: ghostty::Config,
+    /// Configuration for the Neovim backend.
+    pub neovim: neovim::Config,
+    /// Configuration for the Emacs backend.
+    pub emacs: emacs::Config,
+    /// Configuration for the VS Code backend.
+    pub vscode: vscode::Config,
+    /// Configuration for the Zed backend.
+    pub zed: zed::Config,
+    /// Configuration for the Vim backend.
+    pub vim: vim::Config,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            debounce_ms: 250,
+            log: log::Config::default(),
+            gtk: gtk::Config::default(),
+            helix: helix::Config::default(),
+            kitty: kitty::Config::default(),
+            alacritty: alacritty::Config::default(),
+            foot: foot::Config::default(),
+            wezterm: wezterm::Config::default(),
+            ghostty: ghostty::Config::default(),
+            neovim: neovim::Config::default(),
+            emacs: emacs::Config::default(),
+            vscode: vscode::Config::default(),
+            zed: zed::Config::default(),
+            vim: vim::Config::default(),
+        }
+    }
+}
+
+/// An error while loading the configuration.
+#[derive(Debug)]
+pub enum Error {
+    /// The configuration file could not be read.
+    Io(PathBuf, std::io::Error),
+    /// The configuration file has invalid contents.
+    Invalid(PathBuf, toml::de::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(path, error) => write!(f, "Failed to read {}: {error}", path.display()),
+            Error::Invalid(path, error) => {
+                write!(f, "Invalid configuration in {}: {error}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(_, error) => Some(error),
+            Error::Invalid(_, error) => Some(error),
+        }
+    }
+}
+
+/// The default path of the configuration file.
+///
+/// This is `$XDG_CONFIG_HOME/darklightd/config.toml`.
+pub fn default_path() -> PathBuf {
+    xdg::config_home()
+        .join(env!("CARGO_PKG_NAME"))
+        .join("config.toml")
+}
+
+impl Config {
+    /// How long the colour scheme must remain unchanged before backends apply it.
+    pub fn debounce(&self) -> Duration {
+        Duration::from_millis(self.debounce_ms)
+    }
+
+    /// Load configuration from the file at `path`.
+    ///
+    /// If `path` does not exist return the default configuration.
+    pub fn load_from(path: &Path) -> Result<Self, Error> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => {
+                event!(Level::INFO, "Loading configuration from {}", path.display());
+                toml::from_str(&contents).map_err(|error| Error::Invalid(path.to_owned(), error))
+            }
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                event!(
+                    Level::INFO,
+                    "Configuration file {} not found, using default configuration",
+                    path.display()
+                );
+                Ok(Self::default())
+            }
+            Err(error) => Err(Error::Io(path.to_owned(), error)),
+        }
+    }
+}
+
+/// Read pending events from `inotify`, and return whether any event refers to
+/// `file_name`.
+///
+/// Only read what a single `read` returns, to avoid blocking on an empty
+/// queue.
+fn read_inotify_events(inotify: &OwnedFd, file_name: Option<&OsStr>) -> std::io::Result<bool> {
+    let mut buffer = [MaybeUninit::uninit(); 4096];
+    let mut reader = inotify::Reader::new(inotify, &mut buffer);
+    let mut matches = false;
+    loop {
+        let event = reader.next()?;
+        matches |= event
+            .file_name()
+            .map(|name| OsStr::from_bytes(name.to_bytes()))
+            == file_name;
+        if reader.is_buffer_empty() {
+            return Ok(matches);
+        }
+    }
+}
+
+/// Watch for changes to the configuration file at `path`.
+///
+/// Watch the directory containing `path` with inotify, and return a stream
+/// which yields whenever the file at `path` is written, replaced, or deleted.
+/// Watching the directory rather than the file itself makes sure we also catch
+/// editors which save by writing a new file and renaming it.  Do not watch for
+/// created files, to not read a file before its writer finished writing it.
+///
+/// End the stream if reading inotify events fails.
+///
+/// Fails if the directory containing `path` does not exist.
+pub fn watch_changes(path: &Path) -> std::io::Result<BoxStream<'static, ()>> {
+    let directory = path.parent().unwrap_or(path);
+    let file_name = path.file_name().map(ToOwned::to_owned);
+    let inotify = inotify::init(inotify::CreateFlags::CLOEXEC | inotify::CreateFlags::NONBLOCK)?;
+    inotify::add_watch(
+        &inotify,
+        directory,
+        inotify::WatchFlags::CLOSE_WRITE
+            | inotify::WatchFlags::MOVED_TO
+            | inotify::WatchFlags::DELETE,
+    )?;
+    event!(
+        Level::DEBUG,
+        "Watching {} for configuration changes",
+        directory.display()
+    );
+    let inotify = AsyncFd::new(inotify)?;
+    Ok(
+        stream::unfold((inotify, file_name), |(inotify, file_name)| async move {
+            loop {
+                let mut guard = match inotify.readable().await {
+                    Ok(guard) => guard,
+                    Err(error) => {
+                        event!(Level::WARN, "Failed to wait for inotify events: {error}");
+                        return None;
+                    }
+                };
+                match guard
+                    .try_io(|inotify| read_inotify_events(inotify.get_ref(), file_name.as_deref()))
+                {
+                    Ok(Ok(true)) => return Some(((), (inotify, file_name))),
+                    // No matching events, or the queue was empty after all
+                    Ok(Ok(false)) | Err(_) => {}
+                    Ok(Err(error)) => {
+                        // Don't spin on an error which will likely persist
+                        event!(
+                            Level::WARN,
+                            "Failed to read inotify events, not watching for configuration changes anymore: {error}"
+                        );
+                        return None;
+                    }
+                }
+            }
+        })
+        .boxed(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, time::Duration};
+
+    use futures::StreamExt;
+
+    use super::{Config, Error, watch_changes};
+
+    #[test]
+    fn unknown_top_level_key() {
+        let error = toml::from_str::<Config>("debounce-msec = 100\n").unwrap_err();
+        assert!(
+            error.message().contains("unknown field `debounce-msec`"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn unknown_key_in_backend_table() {
+        let error = toml::from_str::<Config>("[helix]\nenabled = true\nligth-theme = \"foo\"\n")
+            .unwrap_err();
+        assert!(
+            error.message().contains("unknown field `ligth-theme`"),
+            "{error}"
+        );
+        // The error points to the offending table
+        assert!(error.to_string().contains("[helix]"), "{error}");
+    }
+
+    #[test]
+    fn unknown_key_in_kitty_table() {
+        let error = toml::from_str::<Config>(
+            "[kitty]
+listen_on = \"unix:@kitty\"\n",
+        )
+        .unwrap_err();
+        assert!(
+            error.message().contains("unknown field `listen_on`"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn theme_keys_in_backend_table() {
+        let config = toml::from_str::<Config>(
+            "[alacritty]\ndark-theme = \"nord\"\n[alacritty.apply]\nretries = 2\n",
+        )
+        .unwrap();
+        assert_eq!(config.alacritty.themes.dark_theme, "nord");
+        assert_eq!(
+            config.alacritty.themes.light_theme,
+            concat!(env!("CARGO_PKG_NAME"), "-light")
+        );
+        assert_eq!(config.alacritty.apply.retries, 2);
+    }
+
+    #[test]
+    fn unknown_key_in_apply_table() {
+        let error = toml::from_str::<Config>("[log.apply]\nretry = 3\n").unwrap_err();
+        assert!(error.message().contains("unknown field `retry`"), "{error}");
+    }
+
+    #[test]
+    fn invalid_file_names_key_and_path() {
+        let path = std::env::temp_dir().join(format!(
+            "{}-test-invalid-config-{}.toml",
+            env!("CARGO_PKG_NAME"),
+            std::process::id()
+        ));
+        std::fs::write(&path, "[gtk]\nenable = true\n").unwrap();
+        let error = Config::load_from(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(error, Error::Invalid(..)));
+        let message = error.to_string();
+        assert!(message.contains(&path.display().to_string()), "{message}");
+        assert!(message.contains("unknown field `enable`"), "{message}");
+    }
+
+    #[test]
+    fn missing_file_gives_defaults() {
+        let config = Config::load_from(Path::new("/nonexistent/darklightd/config.toml")).unwrap();
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn empty_file_gives_defaults() {
+        assert_eq!(toml::from_str::<Config>("").unwrap(), Config::default());
+    }
+
+    #[tokio::test]
+    async fn watch_changes_of_file() {
+        let directory = std::env::temp_dir().join(format!(
+            "{}-test-watch-{}",
+            env!("CARGO_PKG_NAME"),
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("config.toml");
+        let mut changes = watch_changes(&path).unwrap();
+        let next_change = async |changes: &mut futures::stream::BoxStream<'static, ()>| {
+            tokio::time::timeout(Duration::from_millis(500), changes.next())
+                .await
+                .ok()
+        };
+
+        std::fs::write(directory.join("other.toml"), "").unwrap();
+        assert_eq!(next_change(&mut changes).await, None);
+        std::fs::write(&path, "debounce-ms = 10\n").unwrap();
+        assert_eq!(next_change(&mut changes).await, Some(Some(())));
+        std::fs::write(directory.join(".config.toml.tmp"), "").unwrap();
+        std::fs::rename(directory.join(".config.toml.tmp"), &path).unwrap();
+        assert_eq!(next_change(&mut changes).await, Some(Some(())));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(next_change(&mut changes).await, Some(Some(())));
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,413 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{collections::BTreeSet, fmt::Write as _, io::Write, path::Path, sync::Arc};
+
+use futures::{
+    StreamExt,
+    future::OptionFuture,
+    stream::{self, FusedStream},
+};
+use logcontrol_zbus::{ConnectionBuilderExt, logcontrol::LogControl1};
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    sync::{mpsc, watch},
+    task,
+    time::Interval,
+};
+use tokio_stream::wrappers::SignalStream;
+use tracing::{Level, event};
+use zbus::object_server::InterfaceRef;
+
+use crate::{
+    backend::{
+        self, Applied, BackendTasks, ColorScheme, Outcome, Registry, SpawnOptions, spawn_backends,
+    },
+    config::{self, Config},
+    manager::{self, Manager},
+    monitor::{self, spawn_color_scheme_monitor},
+    portal,
+    scheme::ColorSchemePublisher,
+    state::AppliedSchemes,
+    systemd::{self, Notifier},
+};
+
+/// Create a stream of requests to reload the configuration at `config_path`.
+///
+/// Reload upon `SIGHUP`, and whenever the configuration file changes.
+fn reload_requests(config_path: &Path) -> std::io::Result<impl FusedStream<Item = ()> + use<>> {
+    let config_changes = match config::watch_changes(config_path) {
+        Ok(changes) => changes
+            .inspect(|()| event!(Level::INFO, "Configuration file changed"))
+            .left_stream(),
+        Err(error) => {
+            event!(
+                Level::INFO,
+                "Not watching {} for changes, reload with SIGHUP instead: {error}",
+                config_path.display()
+            );
+            stream::empty().right_stream()
+        }
+    };
+    Ok(stream::select(
+        SignalStream::new(signal(SignalKind::hangup())?)
+            .inspect(|()| event!(Level::INFO, "Received SIGHUP")),
+        config_changes,
+    ))
+}
+
+/// Options for running backends.
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    /// Whether backends should only log what they would do.
+    pub dry_run: bool,
+    /// Whether backends should apply the initial colour scheme even if they
+    /// applied it already.
+    pub force: bool,
+}
+
+impl Options {
+    /// Options for spawning backends from `config`.
+    ///
+    /// Remember applied colour schemes unless in dry run mode, and skip the
+    /// initial colour scheme if `skip_up_to_date` is true.
+    fn spawn_options(self, config: &Config, skip_up_to_date: bool) -> SpawnOptions {
+        SpawnOptions {
+            debounce: config.debounce(),
+            applied_schemes: (!self.dry_run).then(AppliedSchemes::default),
+            skip_up_to_date,
+        }
+    }
+}
+
+/// Track which backends applied the colour scheme, and tell systemd about it.
+struct ServiceStatus {
+    notifier: Arc<Notifier>,
+    /// Backends which did not finish applying their first colour scheme yet.
+    pending: BTreeSet<String>,
+    /// Backends which failed to apply the last colour scheme.
+    failing: BTreeSet<String>,
+}
+
+impl ServiceStatus {
+    fn new(notifier: Arc<Notifier>) -> Self {
+        Self {
+            notifier,
+            pending: BTreeSet::new(),
+            failing: BTreeSet::new(),
+        }
+    }
+
+    /// Wait for the backends named `backends` to apply their first colour scheme.
+    fn backends_started(&mut self, backends: Vec<String>) {
+        self.pending = backends.into_iter().collect();
+        self.failing.clear();
+        self.notifier.notify("STATUS=Waiting for color scheme");
+    }
+
+    /// Forget about `stopped` backends, and wait for `started` backends to apply
+    /// their first colour scheme.
+    fn backends_changed(&mut self, stopped: &[String], started: &[String]) {
+        for backend in stopped {
+            self.pending.remove(backend);
+            self.failing.remove(backend);
+        }
+        self.pending.extend(started.iter().cloned());
+    }
+
+    /// Update the status after a backend applied a colour scheme.
+    ///
+    /// Report the colour scheme, backends which did not apply their first
+    /// colour scheme yet, and failing backends.
+    fn applied(&mut self, applied: &Applied) {
+        self.pending.remove(&applied.backend);
+        if applied.succeeded {
+            self.failing.remove(&applied.backend);
+        } else {
+            self.failing.insert(applied.backend.clone());
+        }
+        let mut status = format!("STATUS=Color scheme {}", applied.color_scheme);
+        if !self.pending.is_empty() {
+            write!(status, ", applying to: {}", join(&self.pending)).unwrap();
+        }
+        if !self.failing.is_empty() {
+            write!(status, ", failing backends: {}", join(&self.failing)).unwrap();
+        }
+        self.notifier.notify(&status);
+    }
+}
+
+/// Join `names` with commas.
+fn join(names: &BTreeSet<String>) -> String {
+    names
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Announce the names of all backends in `registry` on the `manager` interface.
+async fn announce_backends(registry: &Registry, manager: &InterfaceRef<Manager>) {
+    let mut iface = manager.get_mut().await;
+    iface.set_backends(registry.names());
+    if let Err(error) = iface.backends_changed(manager.signal_emitter()).await {
+        event!(Level::WARN, "Failed to announce changed backends: {error}");
+    }
+}
+
+/// Restart backends whose configuration changed between the current `backends`
+/// and `config`.
+///
+/// Stop backends which `config` disables or whose configuration changed, and
+/// start backends which `config` enables or whose configuration changed.  Keep
+/// all other backends running.  Do not wait for stopped backends to finish;
+/// `backends` collects their tasks later, see [`BackendTasks::update`].
+/// Announce the names of all backends on the `manager` interface, and update
+/// `status` accordingly.
+async fn update_backends(
+    backends: &mut BackendTasks,
+    config: &Config,
+    options: Options,
+    manager: &InterfaceRef<Manager>,
+    status: &mut ServiceStatus,
+) {
+    let registry = Registry::from_config(config, options.dry_run);
+    // Our receiver never marks any value as seen, so started backends
+    // immediately apply the current colour scheme.  They must not skip it,
+    // because their configuration changed.
+    let changes = backends.update(&registry, &options.spawn_options(config, false));
+    status.backends_changed(&changes.stopped, &changes.started);
+    announce_backends(&registry, manager).await;
+}
+
+/// Spawn all backends enabled in `config`.
+///
+/// Announce the names of all spawned backends on the `manager` interface, and
+/// wait for them to apply their first colour scheme in `status`.  If
+/// `skip_up_to_date` is true backends skip the first colour scheme if they
+/// applied it already.
+async fn start_backends(
+    config: &Config,
+    options: Options,
+    skip_up_to_date: bool,
+    color_scheme_rx: &watch::Receiver<ColorScheme>,
+    applied_tx: &mpsc::UnboundedSender<Applied>,
+    manager: &InterfaceRef<Manager>,
+    status: &mut ServiceStatus,
+) -> BackendTasks {
+    let registry = Registry::from_config(config, options.dry_run);
+    status.backends_started(registry.names());
+    announce_backends(&registry, manager).await;
+    spawn_backends(
+        &registry,
+        color_scheme_rx,
+        applied_tx,
+        &options.spawn_options(config, skip_up_to_date),
+    )
+}
+
+/// Create an interval for pinging the systemd watchdog, if enabled.
+fn watchdog() -> Option<Interval> {
+    // Ping the watchdog twice per interval, as recommended by sd_watchdog_enabled(3)
+    systemd::watchdog_interval().map(|interval| {
+        event!(
+            Level::INFO,
+            "Pinging systemd watchdog every {:?}",
+            interval / 2
+        );
+        tokio::time::interval(interval / 2)
+    })
+}
+
+/// Log all `failed_tasks`, and fail if there are any.
+fn report_failed_tasks(
+    failed_tasks: &[(task::Id, Box<dyn std::error::Error>)],
+) -> Result<(), Box<dyn std::error::Error>> {
+    event!(
+        Level::INFO,
+        "Teardown complete with {} failed tasks, exiting",
+        failed_tasks.len()
+    );
+    if failed_tasks.is_empty() {
+        Ok(())
+    } else {
+        // Log all failed tasks and
+        for (id, error) in failed_tasks {
+            event!(Level::ERROR, task.id = %id, "Task {id} failed: {error}");
+        }
+        Err(std::io::Error::other(format!("{} tasks failed", failed_tasks.len())).into())
+    }
+}
+
+/// Run the daemon.
+///
+/// Load the configuration, connect to the bus, and serve `log_control` and the
+/// manager interface.  Then monitor the colour scheme and feed it to all
+/// backends enabled in the configuration, until asked to terminate.
+pub async fn run<C>(log_control: C, options: Options) -> Result<(), Box<dyn std::error::Error>>
+where
+    C: LogControl1 + Send + Sync + 'static,
+{
+    let config_path = config::default_path();
+    let mut config = Config::load_from(&config_path).inspect_err(|error| {
+        event!(Level::ERROR, "{error}");
+    })?;
+
+    let (publisher, color_scheme_rx) = ColorSchemePublisher::new();
+
+    let connection = zbus::connection::Builder::session()?
+        .serve_log_control(logcontrol_zbus::LogControl1::new(log_control))?
+        .serve_at(manager::OBJECT_PATH, Manager::new(publisher.clone()))?
+        .name("de.swsnr.darklightd")?
+        .build()
+        .await?;
+    event!(Level::INFO, "Connected to bus");
+
+    let manager = connection
+        .object_server()
+        .interface::<_, Manager>(manager::OBJECT_PATH)
+        .await?;
+    let notifier = Arc::new(Notifier::from_env());
+    let mut status = ServiceStatus::new(notifier.clone());
+    let (applied_tx, mut applied_rx) = mpsc::unbounded_channel();
+    let mut backends = start_backends(
+        &config,
+        options,
+        !options.force,
+        &color_scheme_rx,
+        &applied_tx,
+        &manager,
+        &mut status,
+    )
+    .await;
+    let notifier_handle =
+        manager::spawn_property_notifier(connection.clone(), color_scheme_rx.clone());
+    let mut monitor_handle = spawn_color_scheme_monitor(connection.clone(), publisher, notifier);
+    // We own our bus name, and backends wait for the colour scheme.  Do not
+    // wait for backends to apply it: the settings portal may not be around
+    // yet, and backends may retry for a long time.  Report their progress in
+    // the status instead.
+    status.notifier.notify("READY=1");
+
+    let mut termination_signals = stream::select(
+        SignalStream::new(signal(SignalKind::interrupt())?)
+            .inspect(|()| event!(Level::INFO, "Received SIGINT")),
+        SignalStream::new(signal(SignalKind::terminate())?)
+            .inspect(|()| event!(Level::INFO, "Received SIGTERM")),
+    );
+
+    let mut reload_requests = reload_requests(&config_path)?;
+
+    let mut watchdog = watchdog();
+
+    let mut failed_tasks: Vec<(task::Id, Box<dyn std::error::Error>)> = Vec::new();
+
+    loop {
+        tokio::select! {
+            Some(applied) = applied_rx.recv() => status.applied(&applied),
+            Some(_) = OptionFuture::from(watchdog.as_mut().map(Interval::tick)) => {
+                status.notifier.notify("WATCHDOG=1");
+            }
+            () = termination_signals.select_next_some() => {
+                event!(Level::INFO, "Asked to terminate, aborting settings monitor");
+                monitor_handle.abort();
+                break;
+            }
+            () = reload_requests.select_next_some() => {
+                match Config::load_from(&config_path) {
+                    Err(error) => {
+                        event!(Level::ERROR, "Failed to reload configuration, keeping current configuration: {error}");
+                    }
+                    Ok(new_config) if new_config == config => {
+                        event!(Level::INFO, "Configuration unchanged");
+                    }
+                    Ok(new_config) => {
+                        event!(Level::INFO, "Configuration changed, restarting changed backends");
+                        config = new_config;
+                        update_backends(&mut backends, &config, options, &manager, &mut status).await;
+                    }
+                }
+            }
+            result = &mut monitor_handle => {
+                match result {
+                    // Track if the monitor task panicked or returned an error resulted
+                    Err(error) if error.is_panic() => failed_tasks.push((error.id(), error.into())),
+                    Ok(Err(error)) => failed_tasks.push((monitor_handle.id(), error.into())),
+                    _ => {}
+                }
+                break;
+            }
+            Some(result) = backends.join_next_with_id() => {
+                match result {
+                    // Track if the backend panicked or was aborted; we do not abort
+                    // backends so a backend being aborted is an error.
+                    Err(error) => failed_tasks.push((error.id(), error.into())),
+                    Ok((id, Err(error))) => failed_tasks.push((id, error.into())),
+                    Ok((_, Ok(()))) => {}
+                }
+                // Abort monitoring if a backend failed.  We do not abort
+                // backends, because we'd like those that are still running to
+                // properly finish applying the last colour scheme change.
+                monitor_handle.abort();
+                break;
+            }
+        }
+    }
+
+    status.notifier.notify("STOPPING=1");
+    notifier_handle.abort();
+    // The interface reference holds on to the connection; graceful shutdown
+    // waits for all references to the connection to go away.
+    drop(manager);
+    event!(Level::INFO, "Shutting down D-Bus connection");
+    connection.graceful_shutdown().await;
+
+    event!(Level::INFO, "Waiting for all backends to finish");
+    // Wait until applying the last scheme change is finished
+    failed_tasks.extend(backends.stop().await);
+
+    report_failed_tasks(&failed_tasks)
+}
+
+/// Apply the current colour scheme once.
+///
+/// Load the configuration, read the current colour scheme from the settings
+/// portal, and apply it once to all backends enabled in the configuration.
+///
+/// Print the result of each backend, and fail if any backend failed.
+pub async fn apply_once(options: Options) -> Result<(), Box<dyn std::error::Error>> {
+    let config = Config::load_from(&config::default_path()).inspect_err(|error| {
+        event!(Level::ERROR, "{error}");
+    })?;
+    let connection = zbus::Connection::session().await?;
+    let settings = portal::SettingsProxy::new(&connection).await?;
+    let color_scheme = monitor::read_color_scheme(&settings).await?;
+    event!(Level::INFO, "Applying color scheme {color_scheme:?} once");
+
+    let registry = Registry::from_config(&config, options.dry_run);
+    let spawn_options = options.spawn_options(&config, !options.force);
+    let results = backend::apply_once(&registry, color_scheme, &spawn_options).await;
+    let mut stdout = std::io::stdout().lock();
+    let mut failed = 0;
+    for (name, result) in &results {
+        match result {
+            Ok(Outcome::UpToDate) => writeln!(stdout, "{name}: already applied {color_scheme}")?,
+            Ok(Outcome::Applied) if options.dry_run => {
+                writeln!(stdout, "{name}: would apply {color_scheme}")?;
+            }
+            Ok(Outcome::Applied) => writeln!(stdout, "{name}: applied {color_scheme}")?,
+            Err(error) => {
+                failed += 1;
+                writeln!(stdout, "{name}: failed: {error}")?;
+            }
+        }
+    }
+    if failed == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::other(format!("{failed} of {} backends failed", results.len())).into())
+    }
+}
@@ -0,0 +1,126 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::io::Write;
+
+use zbus::fdo;
+
+use crate::{
+    backend::{self, ColorScheme, Diagnosis, Registry},
+    config::{self, Config},
+    portal,
+};
+
+static PORTAL_FIX: &str = "Install xdg-desktop-portal together with a portal implementation for your desktop, e.g. xdg-desktop-portal-gnome or xdg-desktop-portal-kde";
+
+/// Check whether the settings portal is reachable and supports `ReadOne`.
+async fn diagnose_portal() -> Diagnosis {
+    let connection = match zbus::Connection::session().await {
+        Ok(connection) => connection,
+        Err(error) => {
+            return Diagnosis::failed(
+                format!("Failed to connect to the session bus: {error}"),
+                "Run darklightd within a desktop session with a D-Bus session bus",
+            );
+        }
+    };
+    let settings = match portal::SettingsProxy::new(&connection).await {
+        Ok(settings) => settings,
+        Err(error) => {
+            return Diagnosis::failed(
+                format!("Failed to create proxy for settings portal: {error}"),
+                PORTAL_FIX,
+            );
+        }
+    };
+    match settings
+        .read_one("org.freedesktop.appearance", "color-scheme")
+        .await
+    {
+        Ok(value) => match u32::try_from(value) {
+            Ok(value) => Diagnosis::Passed(format!(
+                "Settings portal supports ReadOne and reports color scheme {}",
+                ColorScheme::from(value)
+            )),
+            Err(error) => Diagnosis::failed(
+                format!("Settings portal returned invalid color scheme: {error}"),
+                "Update xdg-desktop-portal and its implementation for your desktop",
+            ),
+        },
+        Err(fdo::Error::ServiceUnknown(_) | fdo::Error::NameHasNoOwner(_)) => {
+            Diagnosis::failed("Settings portal is not running", PORTAL_FIX)
+        }
+        Err(fdo::Error::UnknownMethod(_)) => Diagnosis::failed(
+            "Settings portal does not support ReadOne",
+            "Update xdg-desktop-portal to version 1.15 or newer",
+        ),
+        Err(error) => Diagnosis::failed(
+            format!("Failed to read color scheme from settings portal: {error}"),
+            PORTAL_FIX,
+        ),
+    }
+}
+
+fn print_diagnosis(out: &mut impl Write, name: &str, diagnosis: &Diagnosis) -> std::io::Result<()> {
+    match diagnosis {
+        Diagnosis::Passed(description) => writeln!(out, "{name}: ok: {description}"),
+        Diagnosis::Failed { problem, fix } => {
+            writeln!(out, "{name}: problem: {problem}")?;
+            writeln!(out, "{name}:   fix: {fix}")
+        }
+    }
+}
+
+/// Check the environment of darklightd and all enabled backends.
+///
+/// Check the configuration and the settings portal, and then diagnose every
+/// backend enabled in the configuration.  Print the result of every check,
+/// together with a suggested fix for every failed check.
+///
+/// Fail if any check failed.
+pub async fn doctor() -> Result<(), Box<dyn std::error::Error>> {
+    let config_path = config::default_path();
+    let (config, config_diagnosis) = match Config::load_from(&config_path) {
+        Ok(config) => (
+            Some(config),
+            Diagnosis::Passed(format!(
+                "Configuration at {} is valid",
+                config_path.display()
+            )),
+        ),
+        Err(error) => (
+            None,
+            Diagnosis::failed(
+                error.to_string(),
+                format!("Fix or remove {}", config_path.display()),
+            ),
+        ),
+    };
+
+    let mut diagnoses = vec![
+        ("config".to_owned(), vec![config_diagnosis]),
+        ("portal".to_owned(), vec![diagnose_portal().await]),
+    ];
+    if let Some(config) = config {
+        diagnoses.extend(backend::diagnose(&Registry::from_config(&config, false)).await);
+    }
+
+    let mut stdout = std::io::stdout().lock();
+    let mut failed = 0;
+    for (name, results) in &diagnoses {
+        for diagnosis in results {
+            if diagnosis.is_failed() {
+                failed += 1;
+            }
+            print_diagnosis(&mut stdout, name, diagnosis)?;
+        }
+    }
+    if failed == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::other(format!("{failed} checks failed")).into())
+    }
+}
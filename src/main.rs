@@ -18,28 +18,27 @@
 )]
 #![forbid(unsafe_code)]
 
-use std::io::ErrorKind;
-
-use futures::{StreamExt, stream};
+use backend::ColorScheme;
+use clap::{Parser, Subcommand};
 use logcontrol_tracing::{PrettyLogControl1LayerFactory, TracingLogControl1};
-use logcontrol_zbus::{ConnectionBuilderExt, logcontrol::LogControl1};
-use monitor::spawn_color_scheme_monitor;
-use tokio::{
-    signal::unix::{SignalKind, signal},
-    sync::watch,
-    task,
-};
-use tokio_stream::wrappers::SignalStream;
+use logcontrol_zbus::logcontrol::LogControl1;
 use tracing::{Level, event};
-use tracing_subscriber::{Registry, layer::SubscriberExt};
+use tracing_subscriber::layer::SubscriberExt;
 
 mod backend;
+mod client;
+mod config;
+mod daemon;
+mod doctor;
+mod manager;
 mod monitor;
 mod portal;
+mod scheme;
+mod state;
+mod systemd;
+mod watch;
 mod xdg;
 
-use backend::{ColorScheme, spawn_backends};
-
 /// Setup logging.
 ///
 /// Set up logging to log to journald directly if the process runs under systemd.
@@ -67,100 +66,105 @@ fn setup_logging() -> impl LogControl1 {
     };
     let (control, control_layer) =
         TracingLogControl1::new_auto(PrettyLogControl1LayerFactory, default_level).unwrap();
-    let subscriber = Registry::default().with(env_filter).with(control_layer);
+    let subscriber = tracing_subscriber::Registry::default()
+        .with(env_filter)
+        .with(control_layer);
     tracing::subscriber::set_global_default(subscriber).unwrap();
     control
 }
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let log_control = setup_logging();
-
-    event!(
-        Level::INFO,
-        "{} {}",
-        env!("CARGO_PKG_NAME"),
-        env!("CARGO_PKG_VERSION")
-    );
-
-    let connection = zbus::connection::Builder::session()?
-        .serve_log_control(logcontrol_zbus::LogControl1::new(log_control))?
-        .name("de.swsnr.darklightd")?
-        .build()
-        .await?;
-    event!(Level::INFO, "Connected to bus");
-
-    let (color_scheme_tx, color_scheme_rx) = watch::channel(ColorScheme::NoPreference);
-
-    let mut backends = spawn_backends(&color_scheme_rx);
-    let mut monitor_handle = spawn_color_scheme_monitor(connection.clone(), color_scheme_tx);
-
-    let mut termination_signals = stream::select(
-        SignalStream::new(signal(SignalKind::interrupt())?)
-            .inspect(|()| event!(Level::INFO, "Received SIGINT")),
-        SignalStream::new(signal(SignalKind::terminate())?)
-            .inspect(|()| event!(Level::INFO, "Received SIGTERM")),
-    );
-
-    let mut failed_tasks: Vec<(task::Id, Box<dyn std::error::Error>)> =
-        Vec::with_capacity(backends.len() + 1);
-
-    tokio::select! {
-        () = termination_signals.select_next_some() => {
-            event!(Level::INFO, "Asked to terminate, aborting settings monitor");
-            monitor_handle.abort();
-        }
-        result = &mut monitor_handle => {
-            match result {
-                // Track if the monitor task panicked or returned an error resulted
-                Err(error) if error.is_panic() => failed_tasks.push((error.id(), error.into())),
-                Ok(Err(error)) => failed_tasks.push((monitor_handle.id(), error.into())),
-                _ => {}
-            }
-        }
-        result = backends.join_next() => {
-            if let Some(Err(error)) = result {
-                // Track if the backend panicked or was aborted; we do not abort
-                // backends so a backend being aborted is an error.
-                failed_tasks.push((error.id(), error.into()));
-            }
-            // Abort monitoring if a backend failed; this will close the channel
-            // and thus nominally stop all ongoing backends.  We do not abort
-            // backends, because we'd like those that are still running to properly
-            // finish applying the last colour scheme change.
-            monitor_handle.abort();
-        }
-    }
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Print the current colour scheme of the running daemon.
+    Get,
+    /// Override the colour scheme of the desktop.
+    ///
+    /// The override remains in effect until cleared with `reset`.
+    Set {
+        /// The colour scheme to use.
+        color_scheme: ColorScheme,
+    },
+    /// Clear any override, and follow the colour scheme of the desktop again.
+    Reset,
+    /// Toggle between dark and light colour scheme.
+    ///
+    /// Override the colour scheme with light mode if the current colour scheme
+    /// is dark, and with dark mode otherwise.
+    Toggle,
+    /// Make all backends apply the current colour scheme again.
+    Reapply,
+    /// Show the status of the running daemon.
+    Status,
+    /// Print the colour scheme of the desktop, and then every change to it.
+    ///
+    /// Monitor the settings portal directly; this command does not need the
+    /// daemon.
+    Watch {
+        /// Print every colour scheme as JSON object.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Check the environment of darklightd and all enabled backends.
+    ///
+    /// Print the result of every check, and suggest fixes for problems.  This
+    /// command does not need the daemon.
+    Doctor,
+}
 
-    event!(Level::INFO, "Shutting down D-Bus connection");
-    connection.graceful_shutdown().await;
+/// Update light and dark appearances according to desktop settings.
+///
+/// Without a command run the daemon.  With a command talk to the running daemon
+/// over the session bus.
+#[derive(Debug, Parser)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+struct Args {
+    /// Apply the current colour scheme to all backends once, and exit.
+    ///
+    /// Exit with a non-zero status if any backend failed.
+    #[arg(long)]
+    apply_once: bool,
+    /// Only log what backends would do, without changing anything.
+    #[arg(long)]
+    dry_run: bool,
+    /// Apply the initial colour scheme even to backends which applied it already.
+    ///
+    /// darklightd remembers the colour scheme each backend applied last, and
+    /// by default skips backends which are up to date upon startup.
+    #[arg(long)]
+    force: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
 
-    event!(Level::INFO, "Waiting for all backends to finish");
-    // Wait until applying the last scheme change is finished
-    while let Some(result) = backends.join_next().await {
-        if let Err(error) = result {
-            if error.is_panic() {
-                failed_tasks.push((error.id(), error.into()));
-            }
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    let options = daemon::Options {
+        dry_run: args.dry_run,
+        force: args.force,
+    };
+    match args.command {
+        None if args.apply_once => {
+            setup_logging();
+            daemon::apply_once(options).await
         }
-    }
-
-    event!(
-        Level::INFO,
-        "Teardown complete with {} failed tasks, exiting",
-        failed_tasks.len()
-    );
-    if failed_tasks.is_empty() {
-        Ok(())
-    } else {
-        // Log all failed tasks and
-        for (id, error) in &failed_tasks {
-            event!(Level::ERROR, task.id = %id, "Task {id} failed: {error}");
+        None => {
+            let log_control = setup_logging();
+            event!(
+                Level::INFO,
+                "{} {}",
+                env!("CARGO_PKG_NAME"),
+                env!("CARGO_PKG_VERSION")
+            );
+            daemon::run(log_control, options).await
         }
-        Err(std::io::Error::new(
-            ErrorKind::Other,
-            format!("{} tasks failed", failed_tasks.len()),
-        )
-        .into())
+        Some(Command::Get) => client::get().await,
+        Some(Command::Set { color_scheme }) => client::set(color_scheme).await,
+        Some(Command::Reset) => client::reset().await,
+        Some(Command::Toggle) => client::toggle().await,
+        Some(Command::Reapply) => client::reapply().await,
+        Some(Command::Status) => client::status().await,
+        Some(Command::Watch { json }) => watch::watch(json).await,
+        Some(Command::Doctor) => doctor::doctor().await,
     }
 }
@@ -0,0 +1,150 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use futures::StreamExt;
+use tokio::sync::watch;
+use tokio_stream::wrappers::WatchStream;
+use tracing::{Instrument, Level, event, span};
+use zbus::{fdo, interface};
+
+use crate::{backend::ColorScheme, scheme::ColorSchemePublisher};
+
+/// The object path of the manager object.
+pub const OBJECT_PATH: &str = "/de/swsnr/darklightd";
+
+/// Convert `value` to a colour scheme, rejecting unknown values.
+pub fn color_scheme_from_u32(value: u32) -> fdo::Result<ColorScheme> {
+    match value {
+        0..=2 => Ok(ColorScheme::from(value)),
+        _ => Err(fdo::Error::InvalidArgs(format!(
+            "Invalid color scheme {value}, expected 0, 1, or 2"
+        ))),
+    }
+}
+
+/// The darklightd manager interface.
+///
+/// Expose the current colour scheme over D-Bus, and allow clients to
+/// override the colour scheme of the desktop.
+#[derive(Debug)]
+pub struct Manager {
+    publisher: ColorSchemePublisher,
+    backends: Vec<String>,
+}
+
+impl Manager {
+    /// Create a new manager which changes colour schemes through `publisher`.
+    pub fn new(publisher: ColorSchemePublisher) -> Self {
+        Self {
+            publisher,
+            backends: Vec::new(),
+        }
+    }
+
+    /// Set the names of all running backends.
+    pub fn set_backends(&mut self, backends: Vec<String>) {
+        self.backends = backends;
+    }
+}
+
+#[interface(name = "de.swsnr.darklightd.Manager")]
+impl Manager {
+    /// The current colour scheme.
+    ///
+    /// Uses the same values as the `color-scheme` key in the settings portal,
+    /// i.e. 0 for no preference, 1 for dark, and 2 for light.
+    #[zbus(property)]
+    fn color_scheme(&self) -> u32 {
+        self.publisher.current().into()
+    }
+
+    /// The colour scheme of the desktop.
+    ///
+    /// This differs from the current colour scheme while an override is in effect.
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn desktop_color_scheme(&self) -> u32 {
+        self.publisher.desktop().into()
+    }
+
+    /// Whether an override is in effect.
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn overridden(&self) -> bool {
+        self.publisher.overridden().is_some()
+    }
+
+    /// The names of all running backends.
+    #[zbus(property)]
+    fn backends(&self) -> Vec<String> {
+        self.backends.clone()
+    }
+
+    /// Override the colour scheme of the desktop with `color_scheme`.
+    ///
+    /// The override remains in effect until cleared with `ClearOverride`.
+    #[zbus(name = "Override")]
+    fn set_override(&self, color_scheme: u32) -> fdo::Result<()> {
+        let color_scheme = color_scheme_from_u32(color_scheme)?;
+        event!(
+            Level::INFO,
+            "Overriding color scheme with {color_scheme:?} upon D-Bus request"
+        );
+        self.publisher.set_override(Some(color_scheme));
+        Ok(())
+    }
+
+    /// Clear any override, and return to the colour scheme of the desktop.
+    fn clear_override(&self) {
+        event!(
+            Level::INFO,
+            "Clearing color scheme override upon D-Bus request"
+        );
+        self.publisher.set_override(None);
+    }
+
+    /// Make all backends apply the current colour scheme again.
+    fn reapply(&self) {
+        event!(Level::INFO, "Reapplying color scheme upon D-Bus request");
+        self.publisher.reapply();
+    }
+}
+
+async fn notify_color_scheme_changes(
+    connection: zbus::Connection,
+    color_scheme_rx: watch::Receiver<ColorScheme>,
+) -> zbus::Result<()> {
+    let manager = connection
+        .object_server()
+        .interface::<_, Manager>(OBJECT_PATH)
+        .await?;
+    let mut changes = WatchStream::from_changes(color_scheme_rx);
+    while changes.next().await.is_some() {
+        manager
+            .get()
+            .await
+            .color_scheme_changed(manager.signal_emitter())
+            .await?;
+    }
+    Ok(())
+}
+
+/// Spawn a task to emit property changes of the manager object.
+///
+/// Emit a property change for the `ColorScheme` property whenever
+/// `color_scheme_rx` sees a new colour scheme.
+///
+/// Return a join handle which can be used to abort the task.
+pub fn spawn_property_notifier(
+    connection: zbus::Connection,
+    color_scheme_rx: watch::Receiver<ColorScheme>,
+) -> tokio::task::JoinHandle<zbus::Result<()>> {
+    tokio::spawn(async move {
+        notify_color_scheme_changes(connection, color_scheme_rx)
+            .instrument(
+                span!(Level::INFO, "manager-notifier", task.id = %tokio::task::id()).or_current(),
+            )
+            .await
+    })
+}
@@ -5,81 +5,149 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 use futures::StreamExt;
-use tokio::sync::watch;
 use tracing::{Instrument, Level, event, span};
 
-use crate::{backend::ColorScheme, portal};
+use std::sync::Arc;
 
-async fn receive_color_scheme_changes(
-    settings: portal::SettingsProxy<'_>,
-    sender: watch::Sender<ColorScheme>,
-) -> zbus::Result<()> {
-    let mut changed_stream = settings.receive_setting_changed().await?;
-    while let Some(change) = changed_stream.next().await {
-        let args = change.args()?;
-        if *args.namespace() == "org.freedesktop.appearance" && *args.key() == "color-scheme" {
-            let raw_value = u32::try_from(args.value())?;
-            let color_scheme = ColorScheme::from(raw_value);
-            event!(
-                Level::DEBUG,
-                "org.freedesktop.appearance color-scheme changed to {raw_value} parsed as {color_scheme:?}"
-            );
-            if *sender.borrow() != color_scheme && sender.send(color_scheme).is_err() {
-                // If no one's listening anymore just stop receiving changes
-                return Ok(());
-            }
-        }
+use crate::{backend::ColorScheme, portal, scheme::ColorSchemePublisher, systemd::Notifier};
+
+/// Read the current colour scheme from the settings portal.
+pub async fn read_color_scheme(settings: &portal::SettingsProxy<'_>) -> zbus::Result<ColorScheme> {
+    let reply = settings
+        .read_one("org.freedesktop.appearance", "color-scheme")
+        .await?;
+    Ok(u32::try_from(reply)?.into())
+}
+
+/// Publish `color_scheme` as desktop colour scheme.
+///
+/// If `initial` is true, always publish `color_scheme`, to make the current
+/// scheme apply; otherwise publish it only if it changed.
+///
+/// Return `false` if no one listens to the publisher anymore.
+fn publish_color_scheme(
+    publisher: &ColorSchemePublisher,
+    color_scheme: ColorScheme,
+    initial: bool,
+) -> bool {
+    if initial {
+        publisher.publish_desktop(color_scheme)
+    } else {
+        publisher.desktop() == color_scheme || publisher.publish_desktop(color_scheme)
     }
-    Ok(())
 }
 
 /// Monitor changes to the colour scheme.
 ///
 /// Connect to the settings portal on `connection`, retrieve the current colour
-/// scheme and send it via `sender`.  Then connect to the settings changed signal
-/// of the settings portal and receive further updates of the colour scheme.
+/// scheme and publish it via `publisher`.  Then connect to the settings changed
+/// signal of the settings portal and receive further updates of the colour
+/// scheme.
+///
+/// Whenever the settings portal restarts, read the current colour scheme again
+/// from the new portal.  If the settings portal is not available at startup,
+/// wait for it to start, and then publish its colour scheme.  Tell systemd
+/// via `notifier` while we wait for the settings portal.
 async fn monitor_color_scheme_changes(
     connection: zbus::Connection,
-    sender: watch::Sender<ColorScheme>,
+    publisher: ColorSchemePublisher,
+    notifier: Arc<Notifier>,
 ) -> Result<(), zbus::Error> {
     let settings = portal::SettingsProxy::builder(&connection)
         .cache_properties(zbus::proxy::CacheProperties::No)
         .build()
         .await?;
+    // Subscribe before reading the current colour scheme, to not miss changes
+    // or a restart of the portal in between.
+    let mut changed_stream = settings.receive_setting_changed().await?;
+    let mut owner_changes = settings.inner().receive_owner_changed().await?;
     event!(
         Level::INFO,
         "Connected to settings portal, reading current color scheme from org.freedesktop.appearance color-scheme"
     );
-    let reply = settings
-        .read_one("org.freedesktop.appearance", "color-scheme")
-        .await?;
-    let color_scheme = u32::try_from(reply)?.into();
-    // We deliberately send the initial value to make the current scheme apply
-    if sender.send(color_scheme).is_ok() {
-        event!(Level::INFO, "Watching for color scheme changes");
-        receive_color_scheme_changes(settings, sender).await
-    } else {
-        Ok(())
+    // Whether we still need to publish the initial colour scheme.
+    let mut initial = match read_color_scheme(&settings).await {
+        Ok(color_scheme) => {
+            // We deliberately send the initial value to make the current scheme apply
+            if !publisher.publish_desktop(color_scheme) {
+                return Ok(());
+            }
+            false
+        }
+        Err(error) => {
+            event!(
+                Level::ERROR,
+                "Failed to read color scheme from settings portal, waiting for it to start: {error}"
+            );
+            notifier.notify("STATUS=Waiting for settings portal");
+            true
+        }
+    };
+    event!(Level::INFO, "Watching for color scheme changes");
+    loop {
+        tokio::select! {
+            Some(change) = changed_stream.next() => {
+                let args = change.args()?;
+                if *args.namespace() == "org.freedesktop.appearance" && *args.key() == "color-scheme" {
+                    let raw_value = u32::try_from(args.value())?;
+                    let color_scheme = ColorScheme::from(raw_value);
+                    event!(
+                        Level::DEBUG,
+                        "org.freedesktop.appearance color-scheme changed to {raw_value} parsed as {color_scheme:?}"
+                    );
+                    if !publish_color_scheme(&publisher, color_scheme, initial) {
+                        // If no one's listening anymore just stop receiving changes
+                        return Ok(());
+                    }
+                    initial = false;
+                }
+            }
+            Some(owner) = owner_changes.next() => {
+                let Some(owner) = owner else {
+                    event!(Level::WARN, "Settings portal vanished, waiting for it to come back");
+                    notifier.notify("STATUS=Waiting for settings portal");
+                    continue;
+                };
+                // The colour scheme may have changed while the portal was gone,
+                // so read it again from the new portal.
+                event!(Level::INFO, "Settings portal now owned by {owner}, reading current color scheme");
+                match read_color_scheme(&settings).await {
+                    Ok(color_scheme) => {
+                        if !publish_color_scheme(&publisher, color_scheme, initial) {
+                            return Ok(());
+                        }
+                        initial = false;
+                    }
+                    Err(error) => {
+                        event!(Level::ERROR, "Failed to read color scheme from new settings portal: {error}");
+                    }
+                }
+            }
+            else => return Ok(()),
+        }
     }
 }
 
 /// Spawn a task to monitor changes to the colour scheme.
 ///
 /// Connect to the XDG settings portal using `connection`, monitor changes to the
-/// colour scheme, and publish all changes on the given `sender`.
+/// colour scheme, and publish all changes as desktop colour scheme on the given
+/// `publisher`.  Report a missing settings portal to systemd via `notifier`.
 ///
 /// The monitoring task keeps waiting for signals as long as `connection` is not
-/// forcibly closed.  Note that waiting for signals keeps the connection alive;
-/// it cannot be shutdown gracefully unless the monitor task is aborted.
+/// forcibly closed, even if the settings portal restarts.  Note that waiting
+/// for signals keeps the connection alive; it cannot be shutdown gracefully
+/// unless the monitor task is aborted.
 ///
 /// Return a join handle which can be used to abort the monitoring task, and
 /// upon task completion returns the result of the task.
 pub fn spawn_color_scheme_monitor(
     connection: zbus::Connection,
-    sender: watch::Sender<ColorScheme>,
+    publisher: ColorSchemePublisher,
+    notifier: Arc<Notifier>,
 ) -> tokio::task::JoinHandle<zbus::Result<()>> {
     tokio::spawn(async move {
-        monitor_color_scheme_changes(connection, sender)
+        monitor_color_scheme_changes(connection, publisher, notifier)
             .instrument(
                 span!(Level::INFO, "settings-monitor", task.id = %tokio::task::id()).or_current(),
             )
@@ -0,0 +1,174 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::watch;
+
+use crate::backend::ColorScheme;
+
+#[derive(Debug)]
+struct State {
+    desktop: ColorScheme,
+    overridden: Option<ColorScheme>,
+}
+
+/// Publish colour schemes to backends.
+///
+/// Track the colour scheme of the desktop, and an optional manual override
+/// for it, and publish the effective colour scheme on a watch channel.
+///
+/// While an override is in effect changes to the desktop colour scheme are
+/// tracked, but not published; once the override is cleared the desktop colour
+/// scheme applies again.
+#[derive(Debug, Clone)]
+pub struct ColorSchemePublisher {
+    sender: watch::Sender<ColorScheme>,
+    state: Arc<Mutex<State>>,
+}
+
+impl ColorSchemePublisher {
+    /// Create a new publisher.
+    ///
+    /// Return the publisher and a receiver for the effective colour scheme.
+    /// The receiver considers the initial value as seen, i.e. it only sees
+    /// colour schemes published after this function returned.
+    pub fn new() -> (Self, watch::Receiver<ColorScheme>) {
+        let (sender, receiver) = watch::channel(ColorScheme::NoPreference);
+        let state = State {
+            desktop: ColorScheme::NoPreference,
+            overridden: None,
+        };
+        let publisher = Self {
+            sender,
+            state: Arc::new(Mutex::new(state)),
+        };
+        (publisher, receiver)
+    }
+
+    /// The current colour scheme of the desktop.
+    pub fn desktop(&self) -> ColorScheme {
+        self.state.lock().unwrap().desktop
+    }
+
+    /// The current override, if any.
+    pub fn overridden(&self) -> Option<ColorScheme> {
+        self.state.lock().unwrap().overridden
+    }
+
+    /// The current effective colour scheme.
+    pub fn current(&self) -> ColorScheme {
+        *self.sender.borrow()
+    }
+
+    /// Publish a new `color_scheme` of the desktop.
+    ///
+    /// Unless an override is in effect, send `color_scheme` to all receivers,
+    /// even if it's the same as the current colour scheme.
+    ///
+    /// Return `false` if no one's listening for colour schemes anymore.
+    pub fn publish_desktop(&self, color_scheme: ColorScheme) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.desktop = color_scheme;
+        if state.overridden.is_none() {
+            self.sender.send(color_scheme).is_ok()
+        } else {
+            !self.sender.is_closed()
+        }
+    }
+
+    /// Override the colour scheme of the desktop with `color_scheme`.
+    ///
+    /// If `color_scheme` is `None` clear the override, and publish the current
+    /// colour scheme of the desktop again.
+    pub fn set_override(&self, color_scheme: Option<ColorScheme>) {
+        let mut state = self.state.lock().unwrap();
+        state.overridden = color_scheme;
+        let effective = color_scheme.unwrap_or(state.desktop);
+        self.sender.send_if_modified(|current| {
+            let modified = *current != effective;
+            *current = effective;
+            modified
+        });
+    }
+
+    /// Publish the current colour scheme again.
+    ///
+    /// Tell all receivers that the colour scheme changed, to make backends
+    /// apply the current colour scheme again.
+    pub fn reapply(&self) {
+        self.sender.send_modify(|_| {});
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::ColorScheme;
+
+    use super::ColorSchemePublisher;
+
+    #[test]
+    fn publish_desktop_always_notifies() {
+        let (publisher, mut receiver) = ColorSchemePublisher::new();
+        assert!(!receiver.has_changed().unwrap());
+        assert!(publisher.publish_desktop(ColorScheme::PreferDark));
+        assert_eq!(*receiver.borrow_and_update(), ColorScheme::PreferDark);
+        assert!(publisher.publish_desktop(ColorScheme::PreferDark));
+        assert!(receiver.has_changed().unwrap());
+    }
+
+    #[test]
+    fn desktop_changes_while_overridden_do_not_publish() {
+        let (publisher, mut receiver) = ColorSchemePublisher::new();
+        publisher.set_override(Some(ColorScheme::PreferDark));
+        assert_eq!(*receiver.borrow_and_update(), ColorScheme::PreferDark);
+        assert!(publisher.publish_desktop(ColorScheme::PreferLight));
+        assert!(!receiver.has_changed().unwrap());
+        assert_eq!(publisher.current(), ColorScheme::PreferDark);
+        assert_eq!(publisher.desktop(), ColorScheme::PreferLight);
+        assert_eq!(publisher.overridden(), Some(ColorScheme::PreferDark));
+    }
+
+    #[test]
+    fn clearing_override_publishes_desktop_color_scheme() {
+        let (publisher, mut receiver) = ColorSchemePublisher::new();
+        publisher.set_override(Some(ColorScheme::PreferDark));
+        publisher.publish_desktop(ColorScheme::PreferLight);
+        receiver.mark_unchanged();
+        publisher.set_override(None);
+        assert!(receiver.has_changed().unwrap());
+        assert_eq!(*receiver.borrow_and_update(), ColorScheme::PreferLight);
+        assert_eq!(publisher.overridden(), None);
+    }
+
+    #[test]
+    fn override_with_current_color_scheme_does_not_publish() {
+        let (publisher, receiver) = ColorSchemePublisher::new();
+        publisher.set_override(Some(ColorScheme::NoPreference));
+        assert!(!receiver.has_changed().unwrap());
+        publisher.set_override(None);
+        assert!(!receiver.has_changed().unwrap());
+    }
+
+    #[test]
+    fn reapply_notifies_with_unchanged_color_scheme() {
+        let (publisher, mut receiver) = ColorSchemePublisher::new();
+        publisher.publish_desktop(ColorScheme::PreferDark);
+        receiver.mark_unchanged();
+        publisher.reapply();
+        assert!(receiver.has_changed().unwrap());
+        assert_eq!(*receiver.borrow_and_update(), ColorScheme::PreferDark);
+    }
+
+    #[test]
+    fn publish_desktop_without_receivers() {
+        let (publisher, receiver) = ColorSchemePublisher::new();
+        drop(receiver);
+        assert!(!publisher.publish_desktop(ColorScheme::PreferDark));
+        publisher.set_override(Some(ColorScheme::PreferLight));
+        assert!(!publisher.publish_desktop(ColorScheme::PreferDark));
+    }
+}
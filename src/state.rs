@@ -0,0 +1,193 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{io::ErrorKind, path::PathBuf};
+
+use tracing::{Level, event};
+
+use crate::{
+    backend::{ColorScheme, theme},
+    xdg,
+};
+
+/// A colour scheme a backend applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppliedScheme {
+    /// The colour scheme.
+    pub color_scheme: ColorScheme,
+    /// The fingerprint of the backend configuration the colour scheme was
+    /// applied with.
+    pub fingerprint: String,
+}
+
+/// Format `color_scheme` for the state file.
+///
+/// This is a stable file format, independent of how we display colour schemes
+/// or parse them from the command line.
+fn format_color_scheme(color_scheme: ColorScheme) -> &'static str {
+    match color_scheme {
+        ColorScheme::NoPreference => "default",
+        ColorScheme::PreferDark => "dark",
+        ColorScheme::PreferLight => "light",
+    }
+}
+
+/// Parse a colour scheme from the state file, see [`format_color_scheme`].
+fn parse_color_scheme(value: &str) -> Option<ColorScheme> {
+    match value {
+        "default" => Some(ColorScheme::NoPreference),
+        "dark" => Some(ColorScheme::PreferDark),
+        "light" => Some(ColorScheme::PreferLight),
+        _ => None,
+    }
+}
+
+/// Remembers the colour scheme each backend applied last.
+///
+/// Stores the colour scheme of each backend, together with the fingerprint of
+/// its configuration, in a file named after the backend in a directory, by
+/// default `$XDG_STATE_HOME/darklightd`.
+#[derive(Debug, Clone)]
+pub struct AppliedSchemes {
+    directory: PathBuf,
+}
+
+impl Default for AppliedSchemes {
+    /// Remember applied colour schemes in `$XDG_STATE_HOME/darklightd`.
+    fn default() -> Self {
+        Self::new(xdg::state_home().join(env!("CARGO_PKG_NAME")))
+    }
+}
+
+impl AppliedSchemes {
+    /// Remember applied colour schemes in `directory`.
+    pub fn new(directory: PathBuf) -> Self {
+        Self { directory }
+    }
+
+    fn path(&self, backend: &str) -> PathBuf {
+        self.directory.join(backend)
+    }
+
+    /// Get the colour scheme `backend` applied last.
+    ///
+    /// Return `None` if `backend` never applied a colour scheme, or if we
+    /// failed to read the colour scheme.  Files written before we remembered
+    /// fingerprints have an empty fingerprint.
+    pub fn get(&self, backend: &str) -> Option<AppliedScheme> {
+        let path = self.path(backend);
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => {
+                let mut lines = contents.lines();
+                let value = lines.next().unwrap_or("").trim();
+                let Some(color_scheme) = parse_color_scheme(value) else {
+                    event!(
+                        Level::WARN,
+                        "Invalid color scheme in {}: {value:?}",
+                        path.display()
+                    );
+                    return None;
+                };
+                let fingerprint = lines.next().unwrap_or("").trim().to_owned();
+                Some(AppliedScheme {
+                    color_scheme,
+                    fingerprint,
+                })
+            }
+            Err(error) if error.kind() == ErrorKind::NotFound => None,
+            Err(error) => {
+                event!(Level::WARN, "Failed to read {}: {error}", path.display());
+                None
+            }
+        }
+    }
+
+    /// Remember that `backend` applied `applied`.
+    ///
+    /// Replace the previous colour scheme atomically, see
+    /// [`theme::atomic_write`].
+    pub fn set(&self, backend: &str, applied: &AppliedScheme) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.directory)?;
+        theme::atomic_write(
+            &self.path(backend),
+            &format!(
+                "{}\n{}\n",
+                format_color_scheme(applied.color_scheme),
+                applied.fingerprint
+            ),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_state_dir(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!(
+            "{}-test-{name}-{}",
+            env!("CARGO_PKG_NAME"),
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&directory);
+        directory
+    }
+
+    #[test]
+    fn set_and_get_applied_scheme() {
+        let directory = temp_state_dir("roundtrip");
+        let schemes = AppliedSchemes::new(directory.clone());
+        assert_eq!(schemes.get("helix"), None);
+        let applied = AppliedScheme {
+            color_scheme: ColorScheme::PreferDark,
+            fingerprint: "0123456789abcdef".to_owned(),
+        };
+        schemes.set("helix", &applied).unwrap();
+        assert_eq!(schemes.get("helix"), Some(applied));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn get_applied_scheme_without_fingerprint() {
+        let directory = temp_state_dir("legacy");
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("helix"), "dark\n").unwrap();
+        let schemes = AppliedSchemes::new(directory.clone());
+        assert_eq!(
+            schemes.get("helix"),
+            Some(AppliedScheme {
+                color_scheme: ColorScheme::PreferDark,
+                fingerprint: String::new(),
+            })
+        );
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn color_scheme_format() {
+        for (color_scheme, value) in [
+            (ColorScheme::NoPreference, "default"),
+            (ColorScheme::PreferDark, "dark"),
+            (ColorScheme::PreferLight, "light"),
+        ] {
+            assert_eq!(format_color_scheme(color_scheme), value);
+            assert_eq!(parse_color_scheme(value), Some(color_scheme));
+        }
+    }
+
+    #[test]
+    fn get_applied_scheme_with_invalid_scheme() {
+        let directory = temp_state_dir("invalid");
+        std::fs::create_dir_all(&directory).unwrap();
+        let schemes = AppliedSchemes::new(directory.clone());
+        // Only the exact names, unlike the command line
+        for contents in ["", "\n0123\n", "Dark\n0123\n", "prefer-dark\n0123\n"] {
+            std::fs::write(directory.join("helix"), contents).unwrap();
+            assert_eq!(schemes.get("helix"), None, "{contents:?}");
+        }
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
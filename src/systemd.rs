@@ -0,0 +1,91 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{
+    ffi::OsStr,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::{
+            ffi::OsStrExt,
+            net::{SocketAddr, UnixDatagram},
+        },
+    },
+    path::Path,
+    time::Duration,
+};
+
+use tracing::{Level, event};
+
+/// Notify systemd about the state of the service.
+///
+/// See [`sd_notify(3)`](https://www.freedesktop.org/software/systemd/man/latest/sd_notify.html).
+pub struct Notifier {
+    target: Option<(UnixDatagram, SocketAddr)>,
+}
+
+fn socket_address(path: &OsStr) -> std::io::Result<SocketAddr> {
+    match path.as_bytes().strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name),
+        None => SocketAddr::from_pathname(path),
+    }
+}
+
+impl Notifier {
+    /// Create a notifier for the socket in `$NOTIFY_SOCKET`.
+    ///
+    /// If `$NOTIFY_SOCKET` is not set, i.e. if systemd does not expect any
+    /// notifications, or if connecting fails, the notifier does nothing.
+    pub fn from_env() -> Self {
+        let target = std::env::var_os("NOTIFY_SOCKET").and_then(|path| {
+            socket_address(&path)
+                .and_then(|address| Ok((UnixDatagram::unbound()?, address)))
+                .inspect_err(|error| {
+                    event!(
+                        Level::WARN,
+                        "Failed to connect to notify socket {}: {error}",
+                        Path::new(&path).display()
+                    );
+                })
+                .ok()
+        });
+        Self { target }
+    }
+
+    /// Create a notifier which never notifies systemd.
+    pub fn disabled() -> Self {
+        Self { target: None }
+    }
+
+    /// Send `state` to systemd, e.g. `READY=1`.
+    ///
+    /// Log failures to send the notification, but otherwise ignore them.
+    pub fn notify(&self, state: &str) {
+        if let Some((socket, address)) = &self.target {
+            event!(Level::DEBUG, "Notifying systemd: {state}");
+            if let Err(error) = socket.send_to_addr(state.as_bytes(), address) {
+                event!(
+                    Level::WARN,
+                    "Failed to notify systemd with {state}: {error}"
+                );
+            }
+        }
+    }
+}
+
+/// The interval at which systemd expects watchdog pings from this process.
+///
+/// Return `None` if the watchdog is not enabled for this process, i.e. if
+/// `$WATCHDOG_USEC` is unset or invalid, or if `$WATCHDOG_PID` is set but
+/// names a different process.
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Some(pid) = std::env::var_os("WATCHDOG_PID") {
+        if pid.to_str()?.parse::<u32>().ok()? != std::process::id() {
+            return None;
+        }
+    }
+    let usec = std::env::var_os("WATCHDOG_USEC")?.to_str()?.parse().ok()?;
+    Some(Duration::from_micros(usec)).filter(|interval| !interval.is_zero())
+}
@@ -0,0 +1,44 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::{io::Write, sync::Arc};
+
+use futures::StreamExt;
+use tokio_stream::wrappers::WatchStream;
+
+use crate::{monitor::spawn_color_scheme_monitor, scheme::ColorSchemePublisher, systemd::Notifier};
+
+/// Print the colour scheme of the desktop and all subsequent changes.
+///
+/// Monitor the colour scheme on the settings portal, just like the daemon
+/// does, and print the current colour scheme and then one line for every
+/// change.  If `json` is true print every colour scheme as a JSON object with
+/// a single `color_scheme` key, otherwise print it as plain text.
+///
+/// Return when monitoring the portal fails.
+pub async fn watch(json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let connection = zbus::Connection::session().await?;
+    let (publisher, color_scheme_rx) = ColorSchemePublisher::new();
+    let mut monitor_handle =
+        spawn_color_scheme_monitor(connection, publisher, Arc::new(Notifier::disabled()));
+    let mut changes = WatchStream::from_changes(color_scheme_rx);
+    loop {
+        tokio::select! {
+            result = &mut monitor_handle => {
+                result??;
+                return Ok(());
+            }
+            Some(color_scheme) = changes.next() => {
+                let mut stdout = std::io::stdout().lock();
+                if json {
+                    writeln!(stdout, r#"{{"color_scheme":"{color_scheme}"}}"#)?;
+                } else {
+                    writeln!(stdout, "{color_scheme}")?;
+                }
+            }
+        }
+    }
+}
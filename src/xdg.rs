@@ -6,7 +6,8 @@
 
 use std::path::PathBuf;
 
-fn user_home() -> PathBuf {
+/// Return the home directory of the user, i.e. `$HOME`.
+pub fn user_home() -> PathBuf {
     std::env::var_os("HOME").unwrap().into()
 }
 
@@ -14,3 +15,16 @@ fn user_home() -> PathBuf {
 pub fn config_home() -> PathBuf {
     std::env::var_os("XDG_CONFIG_HOME").map_or_else(|| user_home().join(".config"), Into::into)
 }
+
+/// Return `XDG_STATE_HOME`.
+pub fn state_home() -> PathBuf {
+    std::env::var_os("XDG_STATE_HOME")
+        .map_or_else(|| user_home().join(".local").join("state"), Into::into)
+}
+
+/// Return `XDG_RUNTIME_DIR`, if set.
+///
+/// The XDG base directory specification has no default for this directory.
+pub fn runtime_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_RUNTIME_DIR").map(Into::into)
+}